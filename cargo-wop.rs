@@ -6,6 +6,7 @@
 //! version = "0.1.6"
 //! authors = ["Christopher Prohm"]
 //! edition = "2018"
+//! rust-version = "1.73"
 //!
 //! repository = "https://github.com/chmp/cargo-wop"
 //! description = "Cargo for single-file projects"
@@ -20,6 +21,9 @@
 //! anyhow = "1.0"
 //! serde_json = "1.0"
 //! sha1 = "0.6.0"
+//! sha2 = "0.10"
+//! base64 = "0.22"
+//! zip = { version = "2", default-features = false }
 //! toml = { version = "0.5", features = ["preserve_order"] }
 //!
 //! [cargo-wop]
@@ -62,6 +66,13 @@ mod argparse {
         );
         ensure!(args[0] == "wop", "First argument must be wop");
 
+        if args[1] == "--dry-run" || args[1] == "explain" {
+            let mut inner_args = args;
+            inner_args.remove(1);
+            let inner_args = parse_args(inner_args.into_iter())?;
+            return Ok(Args::DryRun(Box::new(inner_args)));
+        }
+
         if has_extension(args[1].as_os_str()) {
             let res = DefaultAction::new(&args[1])
                 .with_args(args.iter().skip(2))
@@ -96,34 +107,86 @@ mod argparse {
                 );
                 Args::Help
             }
+            "task" => {
+                ensure!(
+                    rest_args.len() == 2,
+                    "The task command expects the source file and the task name as arguments",
+                );
+                Args::Task(PathBuf::from(&rest_args[0]), to_utf8_string(&rest_args[1])?)
+            }
+            "tasks" => {
+                ensure!(
+                    rest_args.len() == 1,
+                    "The tasks command expects the target source file as a single argument",
+                );
+                Args::ListTasks(PathBuf::from(&rest_args[0]))
+            }
+            "installed" => {
+                ensure!(
+                    rest_args.is_empty(),
+                    "The installed command does not understand extra arguments"
+                );
+                Args::ListInstalled
+            }
+            "install" if find_all_stale_flag(rest_args).is_some() => {
+                let mut args = rest_args.to_vec();
+                args.remove(find_all_stale_flag(rest_args).unwrap_or_default());
+                Args::InstallStale(args)
+            }
             "new" => {
-                if rest_args.is_empty() {
+                let (rest_args, vars) = split_template_vars(rest_args)?;
+                if rest_args.is_empty() && vars.is_empty() {
                     Args::ListTemplates
                 } else if rest_args.len() == 2 {
                     let template = rest_args[0]
                         .to_str()
                         .ok_or_else(|| anyhow!("Cannot convert template to utf8 string"))?
                         .to_owned();
-                    let target = PathBuf::from(&rest_args[1]);
-                    Args::New(template, target)
+                    NewFile::new(template, &rest_args[1])
+                        .with_vars(vars)
+                        .into_args()
                 } else {
                     bail!(
                         "Invalid new call: either use 'cargo wop new' to list \
-                        available templates or 'cargo wop new TEMPLATE PATH' \
-                        to create a new file"
+                        available templates or 'cargo wop new TEMPLATE PATH \
+                        [--var KEY=VALUE ...]' to create a new file"
                     );
                 }
             }
-            _ if is_cargo_command(&command) => {
+            // NOTE: any other command is forwarded to cargo, that resolves
+            // builtin commands, aliases and cargo-* executables on the PATH
+            _ if !command.starts_with('-') => {
                 let target = rest_args
-                    .get(0)
+                    .first()
                     .ok_or_else(|| anyhow!("Cargo commands require a target source file"))?;
-                let rest_args = &rest_args[1..];
+                let (targets, rest_args) = if MULTI_SCRIPT_COMMANDS.contains(&command.as_str()) {
+                    split_script_targets(rest_args)
+                } else {
+                    rest_args.split_at(1)
+                };
 
-                CargoCall::new(command, target)
-                    .with_args(rest_args)
-                    .normalize()?
-                    .into_args()
+                let (call, spec) = match split_profile_command(&command) {
+                    Some((command, profile)) => (
+                        CargoCall::new(command, target).with_profile(profile),
+                        find_profile_command_spec(command),
+                    ),
+                    None => (
+                        CargoCall::new(&command, target),
+                        find_command_spec(&command).unwrap_or_default(),
+                    ),
+                };
+                let call = call.with_args(rest_args).normalize(&spec)?;
+
+                let is_multi = targets.len() > 1
+                    || targets.iter().any(is_script_pattern)
+                    || call.options.parallel.is_some()
+                    || call.options.junit.is_some();
+                if is_multi {
+                    let targets = targets.iter().map(PathBuf::from).collect();
+                    Args::MultiCargoCall(call, targets)
+                } else {
+                    call.into_args()
+                }
             }
             _ => bail!(
                 "Unknown command: {}. Use 'cargo wop' help to show available commands.",
@@ -141,8 +204,27 @@ mod argparse {
         GenericCargoCall(CargoCall),
         /// A build step
         BuildCargoCall(CargoCall),
+        /// A doc step that may copy the generated documentation
+        DocCargoCall(CargoCall),
+        /// A package or publish step executed against a publishable layout
+        PackageCargoCall(CargoCall),
         /// A install step that gets passed the manifest dir not the file
         InstallCargoCall(CargoCall),
+        /// Uninstall a previously installed script
+        UninstallCargoCall(CargoCall),
+        /// Build the extension module and package it as a Python wheel
+        WheelCargoCall(CargoCall),
+        /// Execute a cargo call for each of the scripts, that may be patterns,
+        /// and summarize the results
+        MultiCargoCall(CargoCall, Vec<PathBuf>),
+        /// Reinstall all installed scripts that changed since their installation
+        InstallStale(Vec<OsString>),
+        /// List the installed scripts
+        ListInstalled,
+        /// Execute a task configured in the manifest, including its dependencies
+        Task(PathBuf, String),
+        /// List the tasks configured in the manifest
+        ListTasks(PathBuf),
         /// Print out the manifest
         Manifest(PathBuf),
         /// Write the manifest to the current directory
@@ -152,7 +234,9 @@ mod argparse {
         /// Show available templates for new
         ListTemplates,
         /// Create a new file
-        New(String, PathBuf),
+        New(NewFile),
+        /// Print the actions of the wrapped arguments without executing them
+        DryRun(Box<Args>),
     }
 
     #[derive(Debug, PartialEq)]
@@ -186,18 +270,206 @@ mod argparse {
         }
     }
 
+    pub type TemplateVars = Vec<(String, String)>;
+
     #[derive(Debug, PartialEq)]
+    pub struct NewFile {
+        pub template: String,
+        pub target: PathBuf,
+        /// Additional placeholder values passed via `--var KEY=VALUE`
+        pub vars: TemplateVars,
+    }
+
+    impl NewFile {
+        pub fn new<Template, Target>(template: Template, target: Target) -> Self
+        where
+            Template: Into<String>,
+            Target: Into<PathBuf>,
+        {
+            Self {
+                template: template.into(),
+                target: target.into(),
+                vars: Vec::new(),
+            }
+        }
+
+        pub fn with_vars<Vars, Key, Value>(mut self, vars: Vars) -> Self
+        where
+            Vars: IntoIterator<Item = (Key, Value)>,
+            Key: Into<String>,
+            Value: Into<String>,
+        {
+            self.vars
+                .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+            self
+        }
+
+        pub fn into_args(self) -> Args {
+            Args::New(self)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
     pub struct CargoCall {
         pub command: String,
         pub target: PathBuf,
         pub args: Vec<OsString>,
+        pub options: CallOptions,
     }
 
-    #[derive(Debug, PartialEq)]
-    pub struct Exec {
-        pub command: OsString,
-        pub target: PathBuf,
-        pub args: Vec<OsString>,
+    /// Options interpreted by cargo-wop itself, not passed to cargo
+    ///
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct CallOptions {
+        /// The directory to copy the generated documentation into
+        pub doc_dir: Option<PathBuf>,
+        /// The directory to copy the build artifacts into
+        pub out_dir: Option<PathBuf>,
+        /// Fail if a filter entry does not match any build artifact
+        pub strict_filter: bool,
+        /// The rustup toolchain to use
+        pub toolchain: Option<String>,
+        /// Build all configured target triples
+        pub all_targets_triples: bool,
+        /// The python tag of the wheel, if not determined from the interpreter
+        pub python_tag: Option<String>,
+        /// The virtual environment to install the wheel into
+        pub venv: Option<PathBuf>,
+        /// The profile selected by cargo-wop, added to the cargo arguments during execution
+        pub profile: Profile,
+        /// The number of scripts processed concurrently
+        pub parallel: Option<usize>,
+        /// The path of the JUnit report to write
+        pub junit: Option<PathBuf>,
+    }
+
+    /// The build profile selected by cargo-wop
+    ///
+    /// Profiles passed explicitly via `--release` or `--profile` take
+    /// precedence and are passed to cargo as given.
+    ///
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub enum Profile {
+        /// Use the default of cargo
+        #[default]
+        Unset,
+        /// Use the profile configured in the manifest, release if not configured
+        Default,
+        /// Use the profile of the command, e.g., `dev` for `build-debug`
+        Named(String),
+    }
+
+    impl CallOptions {
+        /// Remove the options understood by cargo-wop from the cargo arguments
+        ///
+        /// Only arguments before a `--` are considered. Options extracted
+        /// previously are kept unless given again.
+        ///
+        fn extract(&mut self, command: &str, args: Vec<OsString>) -> Result<Vec<OsString>> {
+            let options = self;
+            let mut rest = Vec::new();
+
+            let mut args = args.into_iter();
+            while let Some(arg) = args.next() {
+                if arg == "--" {
+                    rest.push(arg);
+                    rest.extend(args);
+                    break;
+                }
+
+                if is_wop_flag(command, &arg) {
+                    match arg.to_str() {
+                        Some("--strict-filter") => options.strict_filter = true,
+                        Some("--all-targets-triples") => options.all_targets_triples = true,
+                        _ => unreachable!(),
+                    }
+                    continue;
+                }
+
+                let (flag, value) = match split_flag(&arg) {
+                    Some(flag) if is_wop_option(command, &flag.0) => flag,
+                    _ => {
+                        rest.push(arg);
+                        continue;
+                    }
+                };
+                let value = match value {
+                    Some(value) => value,
+                    None => args
+                        .next()
+                        .ok_or_else(|| anyhow!("{} requires an argument", flag))?,
+                };
+
+                match flag.as_str() {
+                    "--doc-dir" => options.doc_dir = Some(PathBuf::from(value)),
+                    "--out-dir" => options.out_dir = Some(PathBuf::from(value)),
+                    "--toolchain" => options.toolchain = Some(to_utf8_string(&value)?),
+                    "--python-tag" => options.python_tag = Some(to_utf8_string(&value)?),
+                    "--venv" => options.venv = Some(PathBuf::from(value)),
+                    "--parallel" => {
+                        let parallel = to_utf8_string(&value)?
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|parallel| *parallel > 0)
+                            .ok_or_else(|| anyhow!("--parallel requires a positive integer"))?;
+                        options.parallel = Some(parallel);
+                    }
+                    "--junit" => options.junit = Some(PathBuf::from(value)),
+                    _ => unreachable!(),
+                }
+            }
+
+            Ok(rest)
+        }
+    }
+
+    /// The options interpreted by cargo-wop as (flag, commands), `*` matches all commands
+    ///
+    const WOP_OPTIONS: &[(&str, &[&str])] = &[
+        ("--doc-dir", &["doc"]),
+        ("--out-dir", &["build", "wheel"]),
+        ("--python-tag", &["wheel"]),
+        ("--venv", &["wheel"]),
+        ("--toolchain", &["*"]),
+        ("--parallel", MULTI_SCRIPT_COMMANDS),
+        ("--junit", MULTI_SCRIPT_COMMANDS),
+    ];
+
+    /// The commands that accept multiple scripts
+    ///
+    const MULTI_SCRIPT_COMMANDS: &[&str] = &["test", "check", "clippy"];
+
+    /// The flags without values interpreted by cargo-wop as (flag, commands)
+    ///
+    const WOP_FLAGS: &[(&str, &[&str])] = &[
+        ("--strict-filter", &["build"]),
+        ("--all-targets-triples", &["build"]),
+    ];
+
+    fn is_wop_flag(command: &str, arg: &OsStr) -> bool {
+        WOP_FLAGS
+            .iter()
+            .any(|(f, commands)| arg == *f && commands.contains(&command))
+    }
+
+    fn is_wop_option(command: &str, flag: &str) -> bool {
+        WOP_OPTIONS.iter().any(|(f, commands)| {
+            *f == flag && (commands.contains(&command) || commands.contains(&"*"))
+        })
+    }
+
+    /// Split an argument of the form `--flag=value` into its parts
+    ///
+    fn split_flag(arg: &OsStr) -> Option<(String, Option<OsString>)> {
+        let arg = arg.to_str()?;
+        if !arg.starts_with("--") {
+            return None;
+        }
+
+        match arg.split_once('=') {
+            Some((flag, value)) => Some((flag.to_owned(), Some(OsString::from(value)))),
+            None => Some((arg.to_owned(), None)),
+        }
     }
 
     impl CargoCall {
@@ -210,6 +482,7 @@ mod argparse {
                 command: command.into(),
                 target: target.into(),
                 args: Vec::new(),
+                options: CallOptions::default(),
             }
         }
 
@@ -222,13 +495,30 @@ mod argparse {
             self
         }
 
-        /// Normalize the arguments
-        fn normalize(mut self) -> Result<Self> {
-            let (cargo_args, commands_args) = self.split_args();
+        pub fn with_profile<Name>(mut self, profile: Name) -> Self
+        where
+            Name: Into<String>,
+        {
+            self.options.profile = Profile::Named(profile.into());
+            self
+        }
 
-            let mut cargo_args = cargo_args.to_owned();
-            if let "build" | "run" = self.command.as_str() {
-                cargo_args.push(OsString::from("--release"));
+        /// Normalize the arguments according to the command spec
+        pub fn normalize(mut self, spec: &CommandSpec) -> Result<Self> {
+            let (cargo_args, commands_args) = self.split_args(spec);
+            let commands_args = commands_args.to_owned();
+
+            let cargo_args = self.options.extract(&self.command, cargo_args.to_owned())?;
+
+            let explicit_profile = cargo_args.iter().any(|arg| {
+                arg == "--release"
+                    || arg == "--profile"
+                    || arg.to_string_lossy().starts_with("--profile=")
+            });
+            if explicit_profile {
+                self.options.profile = Profile::Unset;
+            } else if spec.release && self.options.profile == Profile::Unset {
+                self.options.profile = Profile::Default;
             }
 
             self.args = if commands_args.is_empty() {
@@ -236,22 +526,16 @@ mod argparse {
             } else {
                 let mut new_args = cargo_args;
                 new_args.push(OsString::from("--"));
-                new_args.extend(commands_args.iter().cloned());
+                new_args.extend(commands_args);
                 new_args
             };
 
-            self.command = match self.command.as_str() {
-                "build-debug" => String::from("build"),
-                "run-debug" => String::from("run"),
-                _ => self.command,
-            };
-
             Ok(self)
         }
 
         /// split the arguments into (cargo, command args)
-        fn split_args(&self) -> (&[OsString], &[OsString]) {
-            if self.command != "run" {
+        fn split_args(&self, spec: &CommandSpec) -> (&[OsString], &[OsString]) {
+            if !spec.split_args {
                 (self.args.as_slice(), &[])
             } else {
                 let splitter = self.args.iter().position(|s| s == "--");
@@ -266,47 +550,260 @@ mod argparse {
         pub fn into_args(self) -> Args {
             match self.command.as_str() {
                 "build" => Args::BuildCargoCall(self),
+                "doc" => Args::DocCargoCall(self),
+                "package" | "publish" => Args::PackageCargoCall(self),
                 "install" => Args::InstallCargoCall(self),
+                "uninstall" => Args::UninstallCargoCall(self),
+                "wheel" => Args::WheelCargoCall(self),
                 _ => Args::GenericCargoCall(self),
             }
         }
     }
 
+    /// Split the leading scripts from the cargo arguments
+    ///
+    /// The first argument is always a script, any following argument only if
+    /// it ends in `.rs`.
+    ///
+    fn split_script_targets(args: &[OsString]) -> (&[OsString], &[OsString]) {
+        let count = args
+            .iter()
+            .skip(1)
+            .take_while(|arg| {
+                let arg = arg.to_string_lossy();
+                !arg.starts_with('-') && arg.ends_with(".rs")
+            })
+            .count();
+        args.split_at(1 + count)
+    }
+
+    /// Whether the file name of the script contains wildcards
+    ///
+    pub fn is_script_pattern(target: impl AsRef<Path>) -> bool {
+        target
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().contains(['*', '?']))
+            .unwrap_or(false)
+    }
+
+    /// Split commands of the form `build-PROFILE` or `run-PROFILE` for builtin profiles
+    ///
+    /// `debug` is an alias for cargo's `dev` profile. Other suffixes are split
+    /// during execution, as they refer to profiles declared in the manifest.
+    ///
+    fn split_profile_command(command: &str) -> Option<(&'static str, String)> {
+        match split_profile_suffix(command)? {
+            (base, "debug") => Some((base, String::from("dev"))),
+            (base, profile @ ("dev" | "release" | "test" | "bench")) => {
+                Some((base, profile.to_owned()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Split a command into `build` or `run` and the suffix after the dash
+    ///
+    pub fn split_profile_suffix(command: &str) -> Option<(&'static str, &str)> {
+        for base in ["build", "run"] {
+            let profile = command
+                .strip_prefix(base)
+                .and_then(|rest| rest.strip_prefix('-'));
+            match profile {
+                Some("") | None => {}
+                Some(profile) => return Some((base, profile)),
+            }
+        }
+        None
+    }
+
+    /// The position of `--all-stale` in the install arguments, it may be given anywhere
+    ///
+    fn find_all_stale_flag(args: &[OsString]) -> Option<usize> {
+        args.iter()
+            .take_while(|arg| *arg != "--")
+            .position(|arg| arg == "--all-stale")
+    }
+
     fn has_extension(s: &OsStr) -> bool {
         AsRef::<Path>::as_ref(s).extension().is_some()
     }
 
-    fn is_cargo_command(command: &str) -> bool {
-        matches!(
-            command,
-            "bench"
-                | "build"
-                | "build-debug"
-                | "check"
-                | "clean"
-                | "clippy"
-                | "fmt"
-                | "install"
-                | "locate-project"
-                | "metadata"
-                | "pkgid"
-                | "run"
-                | "run-debug"
-                | "tree"
-                | "test"
-                | "verify-project"
-        )
+    /// Split the `--var KEY=VALUE` arguments of the new command from the rest
+    ///
+    fn split_template_vars(args: &[OsString]) -> Result<(Vec<OsString>, TemplateVars)> {
+        let mut rest = Vec::new();
+        let mut vars = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let var = if arg == "--var" {
+                args.next()
+                    .ok_or_else(|| anyhow!("--var requires an argument of the form KEY=VALUE"))?
+            } else if arg.to_string_lossy().starts_with("--var=") {
+                arg
+            } else {
+                rest.push(arg.clone());
+                continue;
+            };
+
+            let var = to_utf8_string(var)?;
+            let var = var.strip_prefix("--var=").unwrap_or(&var);
+            let (key, value) = var
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid template variable {}, expected KEY=VALUE", var))?;
+            vars.push((key.to_owned(), value.to_owned()));
+        }
+
+        Ok((rest, vars))
+    }
+
+    /// How the generated project is passed to a cargo command
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ProjectArg {
+        /// Pass `--manifest-path` with the generated manifest
+        ManifestPath,
+        /// Pass `--path` with the project directory, as used by install
+        Path,
+        /// Run the command inside the project directory
+        WorkingDirectory,
+    }
+
+    /// Describe how the arguments of a cargo command are rewritten
+    ///
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct CommandSpec {
+        pub project_arg: ProjectArg,
+        /// Whether the first argument is a subcommand, e.g., `cargo nextest run`
+        pub subcommand: bool,
+        /// Whether arguments are passed to the executable, unless separated
+        /// by `--` from the cargo arguments, e.g., `run`
+        pub split_args: bool,
+        /// Whether the default profile is selected, i.e., `--release` unless
+        /// another profile is configured
+        pub release: bool,
+    }
+
+    impl CommandSpec {
+        /// The spec of commands that only need the manifest path
+        pub const DEFAULT: CommandSpec = CommandSpec {
+            project_arg: ProjectArg::ManifestPath,
+            subcommand: false,
+            split_args: false,
+            release: false,
+        };
+    }
+
+    impl Default for CommandSpec {
+        fn default() -> Self {
+            Self::DEFAULT
+        }
+    }
+
+    /// The commands with known argument handling
+    ///
+    /// Commands not found in this table are forwarded to cargo using the
+    /// default spec, i.e., with the `--manifest-path` argument.
+    ///
+    const CARGO_COMMANDS: &[(&str, CommandSpec)] = &[
+        ("bench", CommandSpec::DEFAULT),
+        (
+            "build",
+            CommandSpec {
+                release: true,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+        ("check", CommandSpec::DEFAULT),
+        ("clean", CommandSpec::DEFAULT),
+        ("clippy", CommandSpec::DEFAULT),
+        ("doc", CommandSpec::DEFAULT),
+        ("expand", CommandSpec::DEFAULT),
+        ("fix", CommandSpec::DEFAULT),
+        ("fmt", CommandSpec::DEFAULT),
+        (
+            "install",
+            CommandSpec {
+                project_arg: ProjectArg::Path,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+        ("locate-project", CommandSpec::DEFAULT),
+        ("metadata", CommandSpec::DEFAULT),
+        ("package", CommandSpec::DEFAULT),
+        (
+            "miri",
+            CommandSpec {
+                subcommand: true,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+        (
+            "nextest",
+            CommandSpec {
+                subcommand: true,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+        ("pkgid", CommandSpec::DEFAULT),
+        ("publish", CommandSpec::DEFAULT),
+        (
+            "run",
+            CommandSpec {
+                split_args: true,
+                release: true,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+        ("rustc", CommandSpec::DEFAULT),
+        ("rustdoc", CommandSpec::DEFAULT),
+        ("tree", CommandSpec::DEFAULT),
+        ("test", CommandSpec::DEFAULT),
+        ("udeps", CommandSpec::DEFAULT),
+        ("update", CommandSpec::DEFAULT),
+        ("verify-project", CommandSpec::DEFAULT),
+        (
+            "wheel",
+            CommandSpec {
+                release: true,
+                ..CommandSpec::DEFAULT
+            },
+        ),
+    ];
+
+    /// Find the spec of a command with known argument handling
+    ///
+    pub fn find_command_spec(command: &str) -> Option<CommandSpec> {
+        CARGO_COMMANDS
+            .iter()
+            .find(|(name, _)| *name == command)
+            .map(|(_, spec)| *spec)
+    }
+
+    /// The spec of a `build-PROFILE` or `run-PROFILE` command
+    ///
+    /// Like `run-debug`, these commands pass all arguments to cargo, i.e.,
+    /// arguments for the script must follow a `--`.
+    ///
+    pub fn find_profile_command_spec(base: &str) -> CommandSpec {
+        CommandSpec {
+            split_args: false,
+            ..find_command_spec(base).unwrap_or_default()
+        }
     }
 }
 
 mod execution {
     use std::{
-        collections::HashMap,
+        collections::{HashMap, HashSet},
         ffi::{OsStr, OsString},
         fs::{self, File},
         io::{BufRead, BufReader},
-        path::{Path, PathBuf},
+        path::{Component, Path, PathBuf},
         process::{Command, Stdio},
+        sync::{Mutex, OnceLock},
+        time::Instant,
     };
 
     use anyhow::{anyhow, bail, ensure, Context, Result};
@@ -317,11 +814,22 @@ mod execution {
     use crate::argparse::DefaultAction;
 
     use super::{
-        argparse::{Args, CargoCall},
+        argparse::{
+            find_command_spec, find_profile_command_spec, is_script_pattern, split_profile_suffix,
+            Args, CargoCall, CommandSpec, NewFile, Profile, ProjectArg,
+        },
+        c_header::{generate_header, HeaderConfig},
         execution_env::ExecutionEnv,
-        manifest_normalization::normalize_manifest,
+        install_records::{InstallRecord, InstallRecords},
+        manifest_normalization::{
+            normalize_manifest, package_manifest, patch_doctest_target, PackageLayout,
+        },
         manifest_parsing::parse_manifest,
+        patterns::{is_pattern, match_pattern, render_pattern},
+        reports::{format_junit, format_summary, ScriptResult},
+        templates::{find_template, find_templates, format_template_list, render_template},
         util::to_utf8_string,
+        wheel::{host_extension_suffix, wheel_file_name, Wheel, WheelTags, PYTHON_TAGS_SCRIPT},
     };
 
     /// helper marco to simplify early returns with options
@@ -335,49 +843,434 @@ mod execution {
     }
 
     pub fn execute_args(args: Args, env: &impl ExecutionEnv) -> Result<i32> {
-        match &args {
+        execute_plan(plan_args(args, env)?, env)
+    }
+
+    /// Print the actions performed for the given arguments without executing them
+    ///
+    pub fn explain_args(args: Args, env: &impl ExecutionEnv) -> Result<i32> {
+        explain_plan(plan_args(args, env)?, env)
+    }
+
+    /// The actions performed for a set of arguments
+    ///
+    /// Planning does not modify the file system. The plan is then either
+    /// executed or, for dry runs, explained.
+    ///
+    enum Plan {
+        /// Execute the arguments one after another, stop at the first failure
+        Steps(Vec<Step>),
+        /// Run a cargo command against the project directory
+        Cargo(CargoCall, ProjectInfo),
+        /// Build the script, once per target triple, and process the artifacts
+        Build(Vec<BuildPlan>),
+        /// Build the extension module and package it as a wheel
+        Wheel(WheelPlan),
+        /// Generate the documentation and copy it into the doc dir, if any
+        Doc(CargoCall, ProjectInfo, Option<PathBuf>),
+        /// Run cargo against the publishable layout, copy the crate file if any
+        Package(Box<PackagePlan>),
+        /// Install the script and record the installation
+        Install(CargoCall, ProjectInfo),
+        /// Uninstall the package and remove the record of the script
+        Uninstall(Command, PathBuf),
+        /// Run the plans of multiple scripts and summarize the results
+        Multi(CargoCall, Vec<(PathBuf, Result<Plan>)>),
+        ListInstalled,
+        ListTasks(ProjectInfo),
+        Manifest(PathBuf),
+        WriteManifest(PathBuf),
+        Help,
+        ListTemplates,
+        New(NewFile),
+        DryRun(Box<Args>),
+    }
+
+    /// A single step of a compound plan, e.g., of a task
+    ///
+    struct Step {
+        description: String,
+        args: Args,
+    }
+
+    /// A single build and the processing of its artifacts
+    ///
+    struct BuildPlan {
+        call: CargoCall,
+        project_info: ProjectInfo,
+        out_dir: PathBuf,
+        /// The profile as named by cargo's output directories
+        profile: String,
+        /// The number of target specific entries preceding the global filter
+        target_filter: usize,
+        c_header: Option<PathBuf>,
+        wasm_bindgen: Option<WasmBindgen>,
+    }
+
+    /// The filter entries applied while copying the artifacts of a build
+    struct FilterUsage {
+        filter: Vec<(String, String)>,
+        /// The number of target specific entries preceding the global filter
+        target_filter: usize,
+        /// The indices of the applied entries
+        used: HashSet<usize>,
+    }
+
+    struct PackagePlan {
+        call: CargoCall,
+        project_info: ProjectInfo,
+        package_info: ProjectInfo,
+        layout: PackageLayout,
+        /// The crate file written by cargo package and its destination
+        crate_file: Option<(PathBuf, PathBuf)>,
+    }
+
+    struct WheelPlan {
+        /// The build of the extension module
+        call: CargoCall,
+        project_info: ProjectInfo,
+        /// The interpreter queried for the tags, unless a python tag is given
+        python: PathBuf,
+        out_dir: PathBuf,
+        /// The virtual environment to install the wheel into
+        venv: Option<PathBuf>,
+    }
+
+    fn plan_args(args: Args, env: &impl ExecutionEnv) -> Result<Plan> {
+        match args {
             Args::DefaultAction(call) => {
-                let project_info = prepare_manifest_dir(&call.target, env)?;
-                let merged_args = merge_default_args(call, &project_info.options.default_action);
+                let project_info = load_project_info(&call.target, env)?;
+                let merged_args = merge_default_args(&call, &project_info.options.default_action);
 
-                eprintln!(":: cargo {}", format_default_args(&merged_args));
+                let description = format!("cargo {}", format_default_args(&merged_args));
                 let args = super::parse_args(merged_args.into_iter())?;
                 assert!(
                     !matches!(args, Args::DefaultAction(_)),
                     "Recursion detected in default action"
                 );
 
-                execute_args(args, env)
+                Ok(Plan::Steps(vec![Step { description, args }]))
             }
+            Args::Task(target, name) => {
+                let project_info = load_project_info(&target, env)?;
+                let mut steps = Vec::new();
+                for step in find_task_steps(&target, &name, &project_info.options)? {
+                    steps.push(Step {
+                        description: format!("cargo {}", format_default_args(&step)),
+                        args: super::parse_args(step.into_iter())?,
+                    });
+                }
+                Ok(Plan::Steps(steps))
+            }
+            Args::ListTasks(target) => Ok(Plan::ListTasks(load_project_info(&target, env)?)),
             Args::GenericCargoCall(call) => {
-                let project_info = prepare_manifest_dir(&call.target, env)?;
-                let exit_code = execute_cargo_call(&call, &project_info)?;
-                Ok(exit_code)
+                let mut project_info = load_project_info(&call.target, env)?;
+                if let Some(args) = resolve_profile_command(&call, &project_info)? {
+                    return plan_args(args, env);
+                }
+
+                let call = normalize_project_call(call, &project_info.options)?;
+                if is_doctest_call(&call, &project_info.options) {
+                    patch_doctest_target(&mut project_info.manifest, &project_info.script_path)?;
+                }
+                let call = resolve_profile(call, &project_info)?;
+                Ok(Plan::Cargo(call, project_info))
             }
             Args::BuildCargoCall(call) => {
-                let project_info = prepare_manifest_dir(&call.target, env)?;
+                let project_info = load_project_info(&call.target, env)?;
+                let call = resolve_profile(call, &project_info)?;
+                let out_dir = find_out_dir(&call, &project_info, env)?;
+
+                let global_filter = project_info.options.filter.len();
+                let mut builds = Vec::new();
+                for (call, project_info, out_dir) in find_builds(&call, &project_info, &out_dir)? {
+                    let options = &project_info.options;
+                    builds.push(BuildPlan {
+                        profile: find_build_profile(&call)?,
+                        target_filter: options.filter.len() - global_filter,
+                        c_header: options.c_header.as_ref().map(|path| out_dir.join(path)),
+                        wasm_bindgen: find_wasm_bindgen(&call, options).cloned(),
+                        call,
+                        project_info,
+                        out_dir,
+                    });
+                }
+                Ok(Plan::Build(builds))
+            }
+            Args::WheelCargoCall(call) => {
+                let project_info = load_project_info(&call.target, env)?;
+                let call = resolve_profile(call, &project_info)?;
+
+                let venv = find_venv(&call, env);
+                let python = find_wheel_python(venv.as_deref(), &project_info.options);
+                let out_dir = find_out_dir(&call, &project_info, env)?;
+
+                Ok(Plan::Wheel(WheelPlan {
+                    call: CargoCall {
+                        command: String::from("build"),
+                        ..call
+                    },
+                    project_info,
+                    python,
+                    out_dir,
+                    venv,
+                }))
+            }
+            Args::DocCargoCall(call) => {
+                let project_info = load_project_info(&call.target, env)?;
+                let doc_dir = find_doc_dir(&call, &project_info, env)?;
+                Ok(Plan::Doc(call, project_info, doc_dir))
+            }
+            Args::PackageCargoCall(call) => {
+                let project_info = load_project_info(&call.target, env)?;
+                let (package_info, layout) = load_package_info(&project_info)?;
+
+                let crate_file = if call.command == "package" {
+                    let crate_file = find_crate_file(&call, &package_info)?;
+                    let script_dir = unwrap_or! {
+                        project_info.script_path.parent(),
+                        bail!("Cannot determine the script directory")
+                    };
+                    let dst = script_dir.join(unwrap_or! {
+                        crate_file.file_name(),
+                        bail!("Invalid crate file name")
+                    });
+                    Some((crate_file, dst))
+                } else {
+                    None
+                };
+
+                Ok(Plan::Package(Box::new(PackagePlan {
+                    call,
+                    project_info,
+                    package_info,
+                    layout,
+                    crate_file,
+                })))
+            }
+            Args::InstallCargoCall(call) => {
+                let project_info = load_project_info(&call.target, env)?;
+                Ok(Plan::Install(call, project_info))
+            }
+            Args::UninstallCargoCall(call) => {
+                let (script_path, package) = find_installed_package(&call.target, env)?;
+                let project_info = load_uninstall_info(&script_path, &package, env)?;
+                Ok(Plan::Uninstall(
+                    build_uninstall_call(&call, &package, &project_info)?,
+                    script_path,
+                ))
+            }
+            Args::MultiCargoCall(call, targets) => {
+                let scripts = expand_script_targets(&targets, &env.get_working_directory())?
+                    .into_iter()
+                    .map(|script| {
+                        let script_call = CargoCall {
+                            target: script.clone(),
+                            ..call.clone()
+                        };
+                        let plan = plan_args(Args::GenericCargoCall(script_call), env);
+                        (script, plan)
+                    })
+                    .collect();
+                Ok(Plan::Multi(call, scripts))
+            }
+            Args::InstallStale(args) => {
+                let records = InstallRecords::load(&find_cache_dir(env)?)?;
+                let mut steps = Vec::new();
+                for record in records.iter().filter(|record| record.is_stale()) {
+                    steps.push(Step {
+                        description: format!("reinstall {}", record.script.display()),
+                        args: build_reinstall_call(record, &args)?,
+                    });
+                }
+                Ok(Plan::Steps(steps))
+            }
+            Args::ListInstalled => Ok(Plan::ListInstalled),
+            Args::Manifest(target) => Ok(Plan::Manifest(target)),
+            Args::WriteManifest(target) => Ok(Plan::WriteManifest(target)),
+            Args::Help => Ok(Plan::Help),
+            Args::ListTemplates => Ok(Plan::ListTemplates),
+            Args::New(new_file) => Ok(Plan::New(new_file)),
+            Args::DryRun(args) => Ok(Plan::DryRun(args)),
+        }
+    }
+
+    fn execute_plan(plan: Plan, env: &impl ExecutionEnv) -> Result<i32> {
+        match plan {
+            Plan::Steps(steps) => {
+                for step in steps {
+                    eprintln!(":: {}", step.description);
+                    let exit_code = execute_args(step.args, env)?;
+                    if exit_code != 0 {
+                        return Ok(exit_code);
+                    }
+                }
+                Ok(0)
+            }
+            Plan::ListTasks(project_info) => {
+                print!("{}", format_task_list(&project_info.options));
+                Ok(0)
+            }
+            Plan::Cargo(call, project_info) => {
+                write_manifest_dir(&project_info)?;
+                execute_cargo_call(&call, &project_info)
+            }
+            Plan::Build(builds) => {
+                let mut used_filters = Vec::new();
+                for build in builds {
+                    let BuildPlan {
+                        call,
+                        project_info,
+                        out_dir,
+                        profile,
+                        target_filter,
+                        c_header,
+                        wasm_bindgen,
+                    } = build;
+
+                    write_manifest_dir(&project_info)?;
+                    let (result, artifacts) = execute_build_call(&call, &project_info)?;
+                    ensure!(
+                        result == 0,
+                        "Error during build. Cannot copy build artifacts"
+                    );
+                    fs::create_dir_all(&out_dir)?;
+                    if let Some(c_header) = c_header {
+                        write_c_header(&project_info, &c_header)?;
+                    }
+                    let ext_suffix = find_extension_suffix(&project_info.options)?;
+                    let (copied, used) = copy_build_artifacts(
+                        &artifacts,
+                        &out_dir,
+                        &project_info.options,
+                        ext_suffix.as_deref(),
+                    )?;
+                    if call.options.strict_filter {
+                        used_filters.push(FilterUsage {
+                            filter: project_info.options.filter.clone(),
+                            target_filter,
+                            used,
+                        });
+                    }
+                    if let Some(wasm_bindgen) = wasm_bindgen {
+                        run_wasm_bindgen(&wasm_bindgen, &project_info, &artifacts, &out_dir)?;
+                    }
+                    run_post_build_hooks(&project_info, &copied, &out_dir, &profile)?;
+                }
+                check_strict_filter(&used_filters)?;
+                Ok(0)
+            }
+            Plan::Wheel(plan) => {
+                let project_info = &plan.project_info;
+                let (tags, ext_suffix) = find_wheel_tags(&plan.call, &plan.python)?;
+                write_manifest_dir(project_info)?;
+                let (result, artifacts) = execute_build_call(&plan.call, project_info)?;
+                ensure!(result == 0, "Error during build. Cannot build the wheel");
+
+                let wheel = build_wheel(project_info, &artifacts, tags, &ext_suffix)?;
+                let wheel_path = plan.out_dir.join(wheel.file_name()?);
+                fs::create_dir_all(&plan.out_dir)?;
+                eprintln!(":: write {}", wheel_path.display());
+                wheel.write(&wheel_path)?;
+
+                if let Some(venv) = plan.venv {
+                    let mut command = build_wheel_install_call(&venv, &wheel_path);
+                    eprintln!(":: {}", format_command(&command));
+                    let status = command.status()?;
+                    ensure!(
+                        status.success(),
+                        "Error during installing the wheel into {}",
+                        venv.display()
+                    );
+                }
+                Ok(0)
+            }
+            Plan::Doc(call, project_info, doc_dir) => {
+                write_manifest_dir(&project_info)?;
                 let result = execute_cargo_call(&call, &project_info)?;
                 ensure!(
                     result == 0,
-                    "Error during build. Cannot copy build artifacts"
+                    "Error during generating the documentation. Cannot copy it"
                 );
-                let artifacts = collect_build_artifacts(&call, &project_info)?;
-                copy_build_artifacts(artifacts, std::env::current_dir()?, &project_info.options)?;
+
+                if let Some(doc_dir) = doc_dir {
+                    let output_dir = find_doc_output_dir(&call, &project_info);
+                    eprintln!(":: copy documentation into {}", doc_dir.display());
+                    copy_documentation(&output_dir, &doc_dir, &project_info.options)?;
+                }
                 Ok(0)
             }
-            Args::InstallCargoCall(call) => {
-                let project_info = prepare_manifest_dir(&call.target, env)?;
-                let mut command = Command::new("cargo");
-                command
-                    .arg(call.command.as_str())
-                    .arg("--path")
-                    .arg(&project_info.manifest_dir)
-                    .args(call.args.iter());
+            Plan::Package(plan) => {
+                let PackagePlan {
+                    call,
+                    project_info,
+                    package_info,
+                    layout,
+                    crate_file,
+                } = *plan;
+                write_manifest_dir(&project_info)?;
+                write_package_dir(&package_info, &layout)?;
+
+                let exit_code = execute_cargo_call(&call, &package_info)?;
+                if exit_code != 0 {
+                    return Ok(exit_code);
+                }
 
+                if let Some((crate_file, dst)) = crate_file {
+                    eprintln!(":: copy {} into {}", crate_file.display(), dst.display());
+                    fs::copy(&crate_file, &dst)
+                        .with_context(|| format!("Cannot copy {}", crate_file.display()))?;
+                }
+                Ok(0)
+            }
+            Plan::Install(call, project_info) => {
+                write_manifest_dir(&project_info)?;
+                let exit_code = execute_cargo_call(&call, &project_info)?;
+                if exit_code == 0 {
+                    let record = InstallRecord::new(
+                        &project_info.script_path,
+                        package_name(&project_info.manifest)?,
+                        binary_names(&project_info.manifest)?,
+                    )?;
+                    update_install_records(env, |records| records.insert(record))?;
+                }
+                Ok(exit_code)
+            }
+            Plan::Uninstall(mut command, script_path) => {
                 let exit_code = command.status()?.code().unwrap_or_default();
+                if exit_code == 0 {
+                    update_install_records(env, |records| records.remove(&script_path))?;
+                }
+                Ok(exit_code)
+            }
+            Plan::Multi(call, scripts) => {
+                let commands = scripts
+                    .into_iter()
+                    .map(|(script, plan)| (script, plan.and_then(prepare_script_call)))
+                    .collect::<Vec<_>>();
+                let results =
+                    run_script_calls(&call.command, commands, call.options.parallel.unwrap_or(1));
+
+                print!("{}", format_summary(&results));
+                if let Some(junit) = &call.options.junit {
+                    eprintln!(":: write JUnit report {}", junit.display());
+                    fs::write(junit, format_junit(&results))
+                        .with_context(|| format!("Cannot write {}", junit.display()))?;
+                }
+
+                let exit_code = results
+                    .iter()
+                    .map(|result| result.exit_code)
+                    .find(|exit_code| *exit_code != 0)
+                    .unwrap_or_default();
                 Ok(exit_code)
             }
-            Args::Manifest(target) => {
+            Plan::ListInstalled => {
+                let records = InstallRecords::load(&find_cache_dir(env)?)?;
+                print!("{}", records.format());
+                Ok(0)
+            }
+            Plan::Manifest(target) => {
                 let file =
                     File::open(target.as_path()).context("Error while opening manifest path")?;
                 let manifest = parse_manifest(file).context("Error while parsing manifest path")?;
@@ -387,7 +1280,7 @@ mod execution {
                 print!("{}", toml::to_string(&manifest)?);
                 Ok(0)
             }
-            Args::WriteManifest(target) => {
+            Plan::WriteManifest(target) => {
                 let env = super::execution_env::LocalEnv::from_env(env);
                 let file =
                     File::open(target.as_path()).context("Error while opening manifest path")?;
@@ -401,18 +1294,21 @@ mod execution {
 
                 Ok(0)
             }
-            Args::Help => {
+            Plan::Help => {
                 println!("{}", super::text::HELP);
                 Ok(0)
             }
-            Args::ListTemplates => {
-                println!("{}", super::text::HELP_TEMPLATES);
+            Plan::ListTemplates => {
+                let templates = find_templates(env)?;
+                print!("{}", format_template_list(&templates, env));
                 Ok(0)
             }
-            Args::New(template, target) => {
+            Plan::New(new_file) => {
                 use std::io::Write;
 
-                let source = render_new_file(template, target)?;
+                let target = &new_file.target;
+                let template = find_template(&new_file.template, env)?;
+                let source = render_template(&template.load()?, target, &new_file.vars)?;
 
                 ensure!(
                     !target.exists(),
@@ -426,816 +1322,6496 @@ mod execution {
 
                 Ok(0)
             }
+            Plan::DryRun(args) => explain_args(*args, env),
         }
     }
 
-    fn merge_default_args(
-        call: &DefaultAction,
-        default_action: &Option<Vec<String>>,
-    ) -> Vec<OsString> {
-        let mut full_args = Vec::new();
-        full_args.push(OsString::from("wop"));
-
-        if let Some(default) = default_action.as_ref() {
-            full_args.extend(default.iter().map(OsString::from));
-        } else {
-            full_args.push(OsString::from("run"));
-        };
-        full_args.extend(call.args.iter().cloned());
-        full_args.insert(2, OsString::from(&call.target));
-
-        full_args
-    }
-
-    fn format_default_args(args: &[OsString]) -> String {
-        let mut res = String::new();
-        for (i, arg) in args.iter().enumerate() {
-            if i != 0 {
-                res.push(' ');
-            }
-            res.push_str(arg.to_string_lossy().as_ref());
+    fn explain_plan(plan: Plan, env: &impl ExecutionEnv) -> Result<i32> {
+        match plan {
+            Plan::Steps(steps) => {
+                for step in steps {
+                    println!(":: {}", step.description);
+                    explain_args(step.args, env)?;
+                }
+                Ok(0)
+            }
+            Plan::Cargo(call, project_info) => {
+                explain_project(&project_info);
+                if is_doctest_call(&call, &project_info.options) {
+                    println!(":: add a library target for the doctests");
+                }
+                explain_command(&build_cargo_call_with_args::<&str>(
+                    &call,
+                    &project_info,
+                    &[],
+                )?);
+                Ok(0)
+            }
+            Plan::Build(builds) => {
+                if let Some(build) = builds.first() {
+                    explain_project(&build.project_info);
+                }
+                for build in builds {
+                    let BuildPlan {
+                        call,
+                        project_info,
+                        out_dir,
+                        profile,
+                        c_header,
+                        wasm_bindgen,
+                        ..
+                    } = build;
+
+                    explain_command(&build_build_call(&call, &project_info)?.0);
+                    if let Some(c_header) = c_header {
+                        println!(":: write C header {}", c_header.display());
+                    }
+                    explain_copy_build_artifacts(&project_info, &out_dir);
+                    if let Some(wasm_bindgen) = wasm_bindgen {
+                        let module = find_target_dir(&call, &project_info)
+                            .join(WASM_BINDGEN_TRIPLE)
+                            .join(&profile)
+                            .join(format!("{}.wasm", library_name(&project_info.manifest)));
+                        explain_command(&build_wasm_bindgen_call(
+                            &wasm_bindgen,
+                            &module,
+                            &out_dir,
+                            &project_info,
+                        ));
+                    }
+                    for hook in &project_info.options.post_build {
+                        let artifacts = match (hook.is_per_artifact(), hook.artifacts.as_ref()) {
+                            (true, Some(pattern)) => {
+                                format!(" (for each artifact matching {})", pattern)
+                            }
+                            (true, None) => String::from(" (for each artifact)"),
+                            (false, Some(pattern)) => {
+                                format!(" (once, if an artifact matches {})", pattern)
+                            }
+                            (false, None) => String::new(),
+                        };
+                        println!(
+                            ":: run post-build hook {}{}",
+                            hook.command.join(" "),
+                            artifacts
+                        );
+                    }
+                }
+                Ok(0)
+            }
+            Plan::Wheel(plan) => {
+                explain_project(&plan.project_info);
+                explain_command(&build_build_call(&plan.call, &plan.project_info)?.0);
+
+                // the interpreter is only queried for the tags when building the wheel
+                let tags = match plan.call.options.python_tag.as_ref() {
+                    Some(python_tag) => {
+                        let tags = WheelTags::from_python_tag(python_tag);
+                        format!("{}-{}-{}", tags.python, tags.abi, tags.platform)
+                    }
+                    None => format!("<tags from {}>", plan.python.display()),
+                };
+                let manifest = &plan.project_info.manifest;
+                let wheel_path = plan.out_dir.join(wheel_file_name(
+                    package_name(manifest)?,
+                    package_version(manifest)?,
+                    &tags,
+                )?);
+                println!(":: write {}", wheel_path.display());
+                if let Some(venv) = plan.venv {
+                    explain_command(&build_wheel_install_call(&venv, &wheel_path));
+                }
+                Ok(0)
+            }
+            Plan::Doc(call, project_info, doc_dir) => {
+                explain_project(&project_info);
+                explain_command(&build_cargo_call_with_args::<&str>(
+                    &call,
+                    &project_info,
+                    &[],
+                )?);
+
+                if let Some(doc_dir) = doc_dir {
+                    println!(
+                        ":: copy documentation from {} into {}",
+                        find_doc_output_dir(&call, &project_info).display(),
+                        doc_dir.display(),
+                    );
+                    explain_filter(&project_info.options);
+                }
+                Ok(0)
+            }
+            Plan::Package(plan) => {
+                let PackagePlan {
+                    call,
+                    package_info,
+                    layout,
+                    crate_file,
+                    ..
+                } = *plan;
+                explain_project(&package_info);
+                for (src, dst) in &layout.files {
+                    println!(":: copy {} to {}", src.display(), dst.display());
+                }
+                explain_command(&build_cargo_call_with_args::<&str>(
+                    &call,
+                    &package_info,
+                    &[],
+                )?);
+                if let Some((crate_file, dst)) = crate_file {
+                    println!(":: copy {} into {}", crate_file.display(), dst.display());
+                }
+                Ok(0)
+            }
+            Plan::Install(call, project_info) => {
+                explain_project(&project_info);
+                explain_command(&build_cargo_call_with_args::<&str>(
+                    &call,
+                    &project_info,
+                    &[],
+                )?);
+                println!(
+                    ":: record the installation of {}",
+                    project_info.script_path.display()
+                );
+                Ok(0)
+            }
+            Plan::Uninstall(command, script_path) => {
+                explain_command(&command);
+                println!(
+                    ":: remove the installation record of {}",
+                    script_path.display()
+                );
+                Ok(0)
+            }
+            Plan::Multi(call, scripts) => {
+                for (_, plan) in scripts {
+                    explain_plan(plan?, env)?;
+                }
+                if let Some(parallel) = call.options.parallel {
+                    println!(":: run up to {} scripts in parallel", parallel);
+                }
+                println!(":: print a summary of the results");
+                if let Some(junit) = &call.options.junit {
+                    println!(":: write JUnit report {}", junit.display());
+                }
+                Ok(0)
+            }
+            Plan::WriteManifest(target) => {
+                println!(
+                    ":: write the manifest of {} to Cargo.toml",
+                    target.display()
+                );
+                Ok(0)
+            }
+            Plan::New(new_file) => {
+                println!(
+                    ":: write {} using the template {}",
+                    new_file.target.display(),
+                    new_file.template
+                );
+                Ok(0)
+            }
+            plan @ (Plan::Manifest(_)
+            | Plan::Help
+            | Plan::ListTemplates
+            | Plan::ListInstalled
+            | Plan::ListTasks(_)) => execute_plan(plan, env),
+            Plan::DryRun(args) => explain_args(*args, env),
         }
-        res
     }
 
-    /// Create the new file source
-    ///
-    fn render_new_file(template: &str, target: &Path) -> Result<String> {
-        let template = match template {
-            "--bin" => super::text::TEMPLATE_BIN,
-            "--lib" => super::text::TEMPLATE_LIB,
-            "--pymodule" => super::text::TEMPLATE_PYMODULE,
-            "--wasm" => super::text::TEMPLATE_WASM,
-            _ => bail!("Unknown template '{}'", template),
-        };
+    fn explain_project(project_info: &ProjectInfo) {
+        println!(":: project dir {}", project_info.manifest_dir.display());
+    }
 
-        let repl = |key: &str| -> Result<String> {
-            match key {
-                "NAME" => {
-                    let res = target
-                        .file_stem()
-                        .ok_or_else(|| anyhow!("Cannot get file stem"))?
-                        .to_str()
-                        .ok_or_else(|| anyhow!("Cannot get uf8 name"))?
-                        .to_owned();
-                    Ok(res)
-                }
-                _ => bail!("Unknown pattern {}", key),
-            }
-        };
+    fn explain_command(command: &Command) {
+        let mut env = String::new();
+        // the location variables are set for every call and omitted for brevity
+        let envs = command
+            .get_envs()
+            .filter(|(key, _)| !key.to_string_lossy().starts_with("CARGO_WOP_"));
+        for (key, value) in envs {
+            let value = value.unwrap_or_default().to_string_lossy();
+            env.push_str(&format!("{}={} ", key.to_string_lossy(), value));
+        }
 
-        let source = super::util::format_dynamic(template, repl)?;
-        Ok(source)
+        if let Some(dir) = command.get_current_dir() {
+            println!(
+                ":: {}{} (in {})",
+                env,
+                format_command(command),
+                dir.display()
+            );
+        } else {
+            println!(":: {}{}", env, format_command(command));
+        }
     }
 
-    /// Execute a cargo call
-    ///
-    fn execute_cargo_call(call: &CargoCall, project_info: &ProjectInfo) -> Result<i32> {
-        let exit_code = build_cargo_call_with_args::<&str>(call, project_info, &[])
-            .status()?
-            .code()
-            .unwrap_or_default();
-        Ok(exit_code)
+    fn format_command(command: &Command) -> String {
+        let mut args = vec![command.get_program().to_owned()];
+        args.extend(command.get_args().map(OsStr::to_owned));
+        format_default_args(&args)
     }
 
-    fn build_cargo_call_with_args<S: AsRef<OsStr>>(
-        call: &CargoCall,
-        project_info: &ProjectInfo,
-        extra_args: &[S],
-    ) -> Command {
-        let mut result = Command::new("cargo");
-        result
-            .arg(call.command.as_str())
-            .arg("--manifest-path")
-            .arg(project_info.manifest_path.as_os_str())
-            .args(extra_args)
-            .args(call.args.iter());
+    fn explain_copy_build_artifacts(project_info: &ProjectInfo, to: &Path) {
+        println!(
+            ":: copy build artifacts of {} into {}",
+            project_info.name,
+            to.display()
+        );
+        explain_filter(&project_info.options);
+
+        let options = &project_info.options;
+        match (options.artifact_kind, options.python.as_ref()) {
+            (ArtifactKind::Default, _) => {}
+            (ArtifactKind::PythonExtension, Some(python)) => println!(
+                "::     name dynamic libraries as python extensions (suffix of {})",
+                python
+            ),
+            (ArtifactKind::PythonExtension, None) => {
+                println!("::     name dynamic libraries as python extensions")
+            }
+            (ArtifactKind::NodeAddon, _) => {
+                println!("::     name dynamic libraries as node addons")
+            }
+        }
+    }
 
-        result
+    fn explain_filter(options: &ProjectOptions) {
+        for (src, dst) in &options.filter {
+            if dst.is_empty() {
+                println!("::     skip {}", src);
+            } else {
+                println!("::     rename {} -> {}", src, dst);
+            }
+        }
     }
 
-    /// Prepare the cargo project directory
-    ///
-    /// This commands writes the manifest and copies the source file. After this
-    /// step, cargo calls can be made against this directory.
-    ///
-    fn prepare_manifest_dir(
-        target: impl AsRef<Path>,
-        env: &impl ExecutionEnv,
-    ) -> Result<ProjectInfo> {
-        let target = target.as_ref();
-        let manifest_dir = find_project_dir(target, env)?;
-        let manifest_path = manifest_dir.join("Cargo.toml");
+    fn merge_default_args(
+        call: &DefaultAction,
+        default_action: &Option<Vec<String>>,
+    ) -> Vec<OsString> {
+        let mut full_args = Vec::new();
+        full_args.push(OsString::from("wop"));
 
-        // TODO: get the name from the normalized manifest in case the user has overwritten it
-        let name = to_utf8_string(
-            target
-                .file_stem()
-                .ok_or_else(|| anyhow!("Could not get name"))?,
-        )?;
+        if let Some(default) = default_action.as_ref() {
+            full_args.extend(default.iter().map(OsString::from));
+        } else {
+            full_args.push(OsString::from("run"));
+        };
+        full_args.extend(call.args.iter().cloned());
+        full_args.insert(2, OsString::from(&call.target));
 
-        let manifest = parse_manifest_file(target)?;
-        let options = parse_custom_section(&manifest)?;
-        let normed_manifest = normalize_manifest(manifest, target, env)?;
+        full_args
+    }
 
-        // perform any faillible operations
-        fs::create_dir_all(&manifest_dir)?;
-        fs::write(&manifest_path, toml::to_string(&normed_manifest)?)?;
+    /// Determine the steps of a task and its dependencies in execution order
+    ///
+    /// Each step is returned as the full argument list, e.g., `wop build
+    /// script.rs --release`. Every task is executed at most once. Steps that
+    /// run other tasks, e.g., `["task", "lint"]`, count as dependencies when
+    /// checking for cycles.
+    ///
+    fn find_task_steps(
+        target: &Path,
+        name: &str,
+        options: &ProjectOptions,
+    ) -> Result<Vec<Vec<OsString>>> {
+        let mut order = Vec::new();
+        visit_task(name, options, &mut Vec::new(), &mut order)?;
 
-        return Ok(ProjectInfo {
-            manifest_path,
-            manifest_dir,
-            name,
-            options,
-        });
+        let mut result = Vec::new();
+        for name in order {
+            for step in &options.get_task(name)?.run {
+                let mut args = vec![OsString::from("wop"), OsString::from(&step[0])];
+                args.push(target.as_os_str().to_owned());
+                args.extend(step[1..].iter().map(OsString::from));
+                result.push(args);
+            }
+        }
+        return Ok(result);
+
+        fn visit_task<'a>(
+            name: &'a str,
+            options: &'a ProjectOptions,
+            stack: &mut Vec<&'a str>,
+            order: &mut Vec<&'a str>,
+        ) -> Result<()> {
+            if order.contains(&name) {
+                return Ok(());
+            }
+            if stack.contains(&name) {
+                bail!(
+                    "Cyclic task dependencies: {} -> {}",
+                    stack.join(" -> "),
+                    name
+                );
+            }
 
-        fn parse_manifest_file(path: impl AsRef<Path>) -> Result<Value> {
-            let file = File::open(path)?;
-            parse_manifest(file)
+            stack.push(name);
+            let task = options.get_task(name)?;
+            for dependency in &task.depends {
+                visit_task(dependency, options, stack, order)?;
+            }
+            // nested tasks are executed as separate steps, with their own order
+            for step in &task.run {
+                if let [command, nested, ..] = step.as_slice() {
+                    if command == "task" {
+                        visit_task(nested, options, stack, &mut Vec::new())?;
+                    }
+                }
+            }
+            stack.pop();
+            order.push(name);
+            Ok(())
         }
     }
 
-    /// Parse the custom section and retrieve cargo-wop configuration
-    ///
-    fn parse_custom_section(manifest: &Value) -> Result<ProjectOptions> {
-        let mut res = ProjectOptions::default();
+    fn format_task_list(options: &ProjectOptions) -> String {
+        use std::fmt::Write;
 
-        let section = unwrap_or! { manifest.get("cargo-wop"), return Ok(res) };
+        let mut res = String::new();
+        if options.tasks.is_empty() {
+            writeln!(res, "No tasks configured").unwrap();
+            return res;
+        }
 
-        if let Some(filter) = section.get("filter") {
-            let filter = unwrap_or! { filter.as_table(), bail!("Filter must be table") };
-            for (src, dst) in filter {
-                let dst = unwrap_or! {
-                    dst.as_str(),
-                    bail!("Invalid destination for source {}, must be a string", src)
-                };
-                res.filter.insert(src.to_owned(), dst.to_owned());
+        writeln!(res, "Available tasks:").unwrap();
+        for (name, task) in &options.tasks {
+            write!(res, "- {}", name).unwrap();
+            if let Some(description) = task.description.as_ref() {
+                write!(res, ": {}", description).unwrap();
+            }
+            if !task.depends.is_empty() {
+                write!(res, " (depends on {})", task.depends.join(", ")).unwrap();
             }
+            writeln!(res).unwrap();
         }
+        res
+    }
 
-        if let Some(default_action) = section.get("default-action") {
-            let default_action =
-                unwrap_or! { default_action.as_array(), bail!("Default action must be an array") };
-            let mut converted_action = Vec::new();
-            for item in default_action {
-                let item = unwrap_or! {
-                    item.as_str(),
-                    bail!("Each entry in the default action must be a string")
-                };
-                converted_action.push(item.to_owned());
+    fn format_default_args(args: &[OsString]) -> String {
+        let mut res = String::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i != 0 {
+                res.push(' ');
             }
-
-            res.default_action = Some(converted_action);
+            res.push_str(arg.to_string_lossy().as_ref());
         }
-
-        Ok(res)
+        res
     }
 
-    /// Execute a cargo call and collect any generated artifacts
+    /// Execute a cargo call
     ///
-    fn collect_build_artifacts(
-        call: &CargoCall,
-        project_info: &ProjectInfo,
-    ) -> Result<Vec<String>> {
-        let output = build_cargo_call_with_args(call, project_info, &["--message-format", "json"])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()?;
-
-        let exit_code = output.status.code().unwrap_or_default();
-        ensure!(
-            exit_code == 0,
-            "Error during running cargo. Exit code {}",
-            exit_code
-        );
-
-        let artifacts = parse_build_output(output.stdout.as_slice(), &project_info)?;
-        Ok(artifacts)
+    fn execute_cargo_call(call: &CargoCall, project_info: &ProjectInfo) -> Result<i32> {
+        let exit_code = build_cargo_call_with_args::<&str>(call, project_info, &[])?
+            .status()?
+            .code()
+            .unwrap_or_default();
+        Ok(exit_code)
     }
 
-    /// Parse the output of a cargo build step
+    /// Expand the script patterns relative to the working directory
     ///
-    fn parse_build_output(output: &[u8], project_info: &ProjectInfo) -> Result<Vec<String>> {
-        let mut result = Vec::new();
-        let reader = BufReader::new(output);
-        for line in reader.lines() {
-            let line = line?;
-            let value: JsonValue = serde_json::from_str(&line)?;
-
-            let reason = value
-                .get("reason")
-                .and_then(JsonValue::as_str)
-                .ok_or_else(|| anyhow!("Invalid cargo output reason not a string"))?;
-
-            if reason != "compiler-artifact" {
+    /// Only the file name may contain wildcards. The matches of each pattern
+    /// are sorted and scripts given multiple times are only included once.
+    ///
+    fn expand_script_targets(targets: &[PathBuf], working_dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut result = Vec::<PathBuf>::new();
+        for target in targets {
+            if !is_script_pattern(target) {
+                if !result.contains(target) {
+                    result.push(target.to_owned());
+                }
                 continue;
             }
 
-            let package_id = value
-                .get("package_id")
-                .and_then(JsonValue::as_str)
-                .ok_or_else(|| anyhow!("Invalid compiler-artifact: package_id not a string"))?;
-
-            let needle = format!("{} ", project_info.name);
-            if !package_id.starts_with(&needle) {
-                continue;
+            let pattern = to_utf8_string(unwrap_or! {
+                target.file_name(),
+                bail!("Invalid script pattern {}", target.display())
+            })?;
+            let parent = target.parent().unwrap_or_else(|| Path::new(""));
+
+            let mut matches = Vec::new();
+            let entries = fs::read_dir(working_dir.join(parent))
+                .with_context(|| format!("Cannot expand {}", target.display()))?;
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name();
+                let name = unwrap_or! { name.to_str(), continue };
+                if entry.file_type()?.is_file() && match_pattern(&pattern, name).is_some() {
+                    matches.push(parent.join(name));
+                }
             }
+            ensure!(
+                !matches.is_empty(),
+                "No script matches {}",
+                target.display()
+            );
 
-            let filenames = value
-                .get("filenames")
-                .and_then(JsonValue::as_array)
-                .ok_or_else(|| anyhow!("Invalid compiler-artifact: filenames not an array"))?;
-
-            for filename in filenames {
-                let filename = filename
-                    .as_str()
-                    .ok_or_else(|| anyhow!("Invalid file name not a string"))?;
-                result.push(filename.to_owned());
+            matches.sort();
+            for script in matches {
+                if !result.contains(&script) {
+                    result.push(script);
+                }
             }
         }
         Ok(result)
     }
 
-    fn copy_build_artifacts<I, P, T>(from: I, to: T, options: &ProjectOptions) -> Result<()>
-    where
-        I: IntoIterator<Item = P>,
-        P: AsRef<Path>,
-        T: AsRef<Path>,
-    {
-        for src in from {
-            let src = src.as_ref();
-
-            let src_file_name = unwrap_or! { src.file_name(), bail!("Invalid source filename") };
-            let dst_file_name = if let Some(src_file_name) = src_file_name.to_str() {
-                if let Some(dst_file_name) = options.filter.get(src_file_name) {
-                    OsStr::new(dst_file_name)
-                } else {
-                    OsStr::new(src_file_name)
-                }
-            } else {
-                src_file_name
-            };
-
-            if dst_file_name.is_empty() {
-                continue;
+    /// Prepare the project of a single script of a multi-script call
+    ///
+    fn prepare_script_call(plan: Plan) -> Result<Command> {
+        match plan {
+            Plan::Cargo(call, project_info) => {
+                write_manifest_dir(&project_info)?;
+                build_cargo_call_with_args::<&str>(&call, &project_info, &[])
             }
-
-            let dst = to.as_ref().join(dst_file_name);
-            fs::copy(src, dst)?;
+            _ => bail!("Only cargo commands can be executed for multiple scripts"),
         }
-        Ok(())
     }
 
-    struct ProjectInfo {
-        name: String,
-        manifest_path: PathBuf,
-        manifest_dir: PathBuf,
-        options: ProjectOptions,
-    }
+    /// Run the commands of the scripts with up to `parallel` at the same time
+    ///
+    /// The output of each script is captured and printed as a block once the
+    /// script finishes. Scripts whose command could not be prepared are
+    /// reported as failures.
+    ///
+    fn run_script_calls(
+        command: &str,
+        commands: Vec<(PathBuf, Result<Command>)>,
+        parallel: usize,
+    ) -> Vec<ScriptResult> {
+        let count = commands.len();
+        let queue = Mutex::new(commands.into_iter().enumerate());
+        let results = Mutex::new(Vec::with_capacity(count));
+
+        std::thread::scope(|scope| {
+            for _ in 0..parallel.clamp(1, count.max(1)) {
+                scope.spawn(|| loop {
+                    let item = queue.lock().unwrap().next();
+                    let (idx, (script, cargo_call)) = unwrap_or! { item, break };
+                    let result = run_script_call(command, script, cargo_call);
+                    print_script_result(&result);
+                    results.lock().unwrap().push((idx, result));
+                });
+            }
+        });
 
-    #[derive(Default, Debug)]
-    struct ProjectOptions {
-        /// Rename or skip build artifacts
-        filter: HashMap<String, String>,
-        default_action: Option<Vec<String>>,
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(idx, _)| *idx);
+        results.into_iter().map(|(_, result)| result).collect()
     }
 
-    /// Find the project directory from the supplied file
-    ///
-    fn find_project_dir(source: impl AsRef<Path>, env: &impl ExecutionEnv) -> Result<PathBuf> {
-        let source = source.as_ref();
-        let source = env.normalize(source)?;
+    fn run_script_call(
+        command: &str,
+        script: PathBuf,
+        cargo_call: Result<Command>,
+    ) -> ScriptResult {
+        let start = Instant::now();
+        let output = cargo_call.and_then(|mut cargo_call| {
+            cargo_call
+                .output()
+                .with_context(|| format!("Cannot execute {}", format_command(&cargo_call)))
+        });
+        let (exit_code, stdout, stderr) = match output {
+            Ok(output) => (
+                output.status.code().unwrap_or(1),
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ),
+            Err(err) => (1, String::new(), format!("Error: {:?}\n", err)),
+        };
 
-        let target_name = source
-            .file_stem()
-            .ok_or_else(|| anyhow!("Could not get path stem"))?;
-        let mut target_name = target_name.to_owned();
-        target_name.push("-");
-        target_name.push(&hash_path(source));
+        ScriptResult {
+            script,
+            command: command.to_owned(),
+            exit_code,
+            duration: start.elapsed(),
+            stdout,
+            stderr,
+        }
+    }
 
-        let mut result = find_cache_dir(env)?;
-        result.push(target_name);
+    fn print_script_result(result: &ScriptResult) {
+        use std::io::Write;
+
+        // hold both locks to keep the blocks of parallel scripts together
+        let mut stdout = std::io::stdout().lock();
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(
+            stderr,
+            ":: cargo {} {}",
+            result.command,
+            result.script.display()
+        );
+        let _ = stderr.write_all(result.stderr.as_bytes());
+        let _ = stdout.write_all(result.stdout.as_bytes());
+        let _ = stdout.flush();
+    }
+
+    fn build_uninstall_call(
+        call: &CargoCall,
+        package: &str,
+        project_info: &ProjectInfo,
+    ) -> Result<Command> {
+        let mut result = cargo_command(
+            project_info.options.cargo.as_deref(),
+            find_toolchain(call, project_info),
+        )?;
+        set_cargo_env(&mut result, project_info);
+        result
+            .arg(call.command.as_str())
+            .arg(package)
+            .args(call.args.iter());
 
         Ok(result)
     }
 
-    /// Find the internal cache dir for cargo-wop
+    /// Build the call to reinstall a previously installed script
     ///
-    fn find_cache_dir(env: &impl ExecutionEnv) -> Result<PathBuf> {
-        let mut result = env.get_cargo_home_dir();
-        result.push("wop-cache");
-        Ok(result)
-    }
-
-    fn hash_path(path: impl AsRef<Path>) -> String {
-        let mut hash = Sha1::new();
-        hash.update(path.as_ref().to_string_lossy().as_bytes());
-        let digest = hash.digest();
-        let res = digest.to_string();
-        res[..8].to_string()
+    fn build_reinstall_call(record: &InstallRecord, args: &[OsString]) -> Result<Args> {
+        let spec = find_command_spec("install").unwrap_or_default();
+        let call = CargoCall::new("install", &record.script)
+            .with_args(args)
+            .with_args(["--force"])
+            .normalize(&spec)?
+            .into_args();
+        Ok(call)
     }
 
-    #[cfg(test)]
-    mod test {
-        use super::*;
+    /// Find the package name of an installed script in the installation records
+    ///
+    /// The package is always taken from the record, as the script may have
+    /// been changed or deleted since installation. Deleted scripts cannot be
+    /// canonicalized and are matched by their lexically normalized path.
+    ///
+    fn find_installed_package(target: &Path, env: &impl ExecutionEnv) -> Result<(PathBuf, String)> {
+        let path = env.get_working_directory().join(target);
+        let script_path = if path.exists() {
+            env.normalize(target)?
+        } else {
+            normalize_path(&path)
+        };
 
-        // wrapper to simplify test code
-        fn merge_default_args(call: DefaultAction, default_action: Option<&[&str]>) -> Vec<String> {
-            let default_action = default_action.map(to_strings);
-            super::merge_default_args(&call, &default_action)
-                .iter()
-                .map(|s| OsString::into_string(s.clone()).unwrap())
-                .collect()
-        }
+        let records = InstallRecords::load(&find_cache_dir(env)?)?;
+        let record = unwrap_or! {
+            records.iter().find(|record| record.script == script_path),
+            bail!("{} was not installed with cargo wop install", target.display())
+        };
+        Ok((script_path, record.package.clone()))
+    }
 
-        fn to_strings(data: &[&str]) -> Vec<String> {
-            data.iter().map(|s| (*s).to_owned()).collect()
+    /// Remove `.` and resolve `..` components without accessing the file system
+    ///
+    fn normalize_path(path: &Path) -> PathBuf {
+        let mut result = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    result.pop();
+                }
+                component => result.push(component),
+            }
         }
+        result
+    }
 
-        #[test]
-        fn test_merge_default_args() {
-            assert_eq!(
-                merge_default_args(DefaultAction::new("foo.rs"), None),
-                to_strings(&["wop", "run", "foo.rs"]),
-            );
+    /// Modify the installation records stored in the cache dir
+    ///
+    fn update_install_records(
+        env: &impl ExecutionEnv,
+        update: impl FnOnce(&mut InstallRecords),
+    ) -> Result<()> {
+        let cache_dir = find_cache_dir(env)?;
+        let mut records = InstallRecords::load(&cache_dir)?;
+        update(&mut records);
+        records.store(&cache_dir)
+    }
 
-            assert_eq!(
-                merge_default_args(
-                    DefaultAction::new("foo.rs").with_args(&["--", "hello", "world"]),
-                    None
-                ),
-                to_strings(&["wop", "run", "foo.rs", "--", "hello", "world"]),
-            );
+    /// The package name of a normalized manifest
+    ///
+    fn package_name(manifest: &Value) -> Result<&str> {
+        manifest
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Invalid manifest: package name is not a string"))
+    }
 
-            assert_eq!(
-                merge_default_args(
-                    DefaultAction::new("foo.rs").with_args(&["test", "--", "hello", "world"]),
-                    Some(&[])
-                ),
-                to_strings(&["wop", "test", "foo.rs", "--", "hello", "world"]),
-            );
+    /// The package version of a normalized manifest
+    ///
+    fn package_version(manifest: &Value) -> Result<&str> {
+        manifest
+            .get("package")
+            .and_then(|package| package.get("version"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Invalid manifest: package version is not a string"))
+    }
 
-            assert_eq!(
-                merge_default_args(
-                    DefaultAction::new("foo.rs"),
-                    Some(&["build", "--target", "wasm32-unknown-unknown"])
-                ),
-                to_strings(&[
-                    "wop",
-                    "build",
-                    "foo.rs",
-                    "--target",
-                    "wasm32-unknown-unknown"
-                ]),
-            );
+    /// The names of the binaries of a normalized manifest
+    ///
+    fn binary_names(manifest: &Value) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        let bins = unwrap_or! { manifest.get("bin").and_then(Value::as_array), return Ok(result) };
+        for bin in bins {
+            let name = bin
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("Invalid manifest: binary name is not a string"))?;
+            result.push(name.to_owned());
         }
+        Ok(result)
     }
-}
 
-mod execution_env {
-    use std::path::{Path, PathBuf};
-
-    use anyhow::{bail, Context, Result};
+    /// The toolchain to use, the command line takes precedence over the configuration
+    ///
+    fn find_toolchain<'a>(call: &'a CargoCall, project_info: &'a ProjectInfo) -> Option<&'a str> {
+        call.options
+            .toolchain
+            .as_deref()
+            .or(project_info.options.toolchain.as_deref())
+    }
 
-    /// The environment the command is executed in
+    /// Create the command used to execute cargo
     ///
-    /// It's defined as a trait to mock it out in tests.
+    /// All cargo processes are spawned via this function. The cargo binary is
+    /// taken from `CARGO_WOP_CARGO`, the `cargo` key of the configuration,
+    /// `CARGO` (set by cargo when executing subcommands) or is `cargo`, in this
+    /// order.
     ///
-    pub trait ExecutionEnv: Clone {
-        fn get_cargo_home_dir(&self) -> PathBuf;
-        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf>;
+    fn cargo_command(configured: Option<&str>, toolchain: Option<&str>) -> Result<Command> {
+        let program = select_cargo(
+            std::env::var_os("CARGO_WOP_CARGO"),
+            configured,
+            std::env::var_os("CARGO"),
+            toolchain.is_some(),
+        );
+        let mut result = Command::new(program);
+
+        if let Some(toolchain) = toolchain {
+            ensure_rustup(toolchain)?;
+            result.env("RUSTUP_TOOLCHAIN", toolchain);
+        }
+        Ok(result)
     }
 
-    #[derive(Clone)]
-    pub struct StdExecutionEnv {
-        working_directory: PathBuf,
-        cargo_directory: PathBuf,
+    /// Select the cargo binary
+    ///
+    /// `CARGO` points to the binary of the active toolchain and is therefore
+    /// ignored if a different toolchain is requested.
+    ///
+    fn select_cargo(
+        wop_cargo: Option<OsString>,
+        configured: Option<&str>,
+        cargo: Option<OsString>,
+        has_toolchain: bool,
+    ) -> OsString {
+        wop_cargo
+            .or_else(|| configured.map(OsString::from))
+            .or_else(|| cargo.filter(|_| !has_toolchain))
+            .unwrap_or_else(|| OsString::from("cargo"))
     }
 
-    impl StdExecutionEnv {
-        pub fn new() -> Result<Self> {
-            let this = Self {
-                working_directory: std::env::current_dir()?,
-                cargo_directory: find_cargo_home_dir()?,
-            };
-            Ok(this)
-        }
+    /// Fail with a helpful error if rustup is not available, checked once per process
+    ///
+    fn ensure_rustup(toolchain: &str) -> Result<()> {
+        static HAS_RUSTUP: OnceLock<bool> = OnceLock::new();
+        let has_rustup = *HAS_RUSTUP.get_or_init(|| {
+            Command::new("rustup")
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .map(|status| status.success())
+                .unwrap_or(false)
+        });
+        ensure!(
+            has_rustup,
+            "The toolchain {} was requested, but rustup is not available",
+            toolchain
+        );
+        Ok(())
     }
 
-    impl ExecutionEnv for StdExecutionEnv {
-        fn get_cargo_home_dir(&self) -> PathBuf {
-            self.cargo_directory.clone()
-        }
+    fn build_cargo_call_with_args<S: AsRef<OsStr>>(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+        extra_args: &[S],
+    ) -> Result<Command> {
+        let spec = command_spec(&call.command, &project_info.options);
+        let mut args = call.args.iter();
 
-        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
-            let p = self.working_directory.join(path);
-            let p = p
-                .canonicalize()
-                .with_context(|| format!("Cannot canonicalize {}", p.display()))?;
-            Ok(p)
+        let mut result = cargo_command(
+            project_info.options.cargo.as_deref(),
+            find_toolchain(call, project_info),
+        )?;
+        set_cargo_env(&mut result, project_info);
+        result.arg(call.command.as_str());
+        if spec.subcommand {
+            result.args(args.next());
         }
-    }
-
-    #[derive(Clone)]
-    pub struct LocalEnv {
-        cargo_directory: PathBuf,
-    }
 
-    impl LocalEnv {
-        pub fn from_env(env: &impl ExecutionEnv) -> Self {
-            Self {
-                cargo_directory: env.get_cargo_home_dir(),
+        match spec.project_arg {
+            ProjectArg::ManifestPath => {
+                result
+                    .arg("--manifest-path")
+                    .arg(project_info.manifest_path.as_os_str());
+            }
+            ProjectArg::Path => {
+                result.arg("--path").arg(&project_info.manifest_dir);
             }
+            ProjectArg::WorkingDirectory => {
+                result.current_dir(&project_info.manifest_dir);
+            }
+        }
+        if call.command == "run"
+            && spec.project_arg != ProjectArg::WorkingDirectory
+            && project_info.options.run_in == RunIn::ScriptDir
+        {
+            result.current_dir(project_info.script_dir());
         }
+        result.args(extra_args).args(args);
+
+        Ok(result)
     }
 
-    impl ExecutionEnv for LocalEnv {
-        fn get_cargo_home_dir(&self) -> PathBuf {
-            self.cargo_directory.clone()
-        }
+    /// Set the location variables and the configured environment of a cargo call
+    ///
+    fn set_cargo_env(command: &mut Command, project_info: &ProjectInfo) {
+        command
+            .envs(project_info.location_env())
+            .envs(project_info.expand_env(&project_info.options.env))
+            .envs(project_info.expand_env(&project_info.options.build_env));
+    }
 
-        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
-            Ok(path.as_ref().into())
-        }
+    /// Prepare the cargo project directory
+    ///
+    /// This commands writes the manifest. After this step, cargo calls can be
+    /// made against this directory. Test calls get an additional library
+    /// target during planning if doctests are requested.
+    ///
+    fn write_manifest_dir(project_info: &ProjectInfo) -> Result<()> {
+        // perform any faillible operations
+        fs::create_dir_all(&project_info.manifest_dir)?;
+        fs::write(
+            &project_info.manifest_path,
+            toml::to_string(&project_info.manifest)?,
+        )?;
+        Ok(())
     }
 
-    /// Find the cargo home
+    /// Whether the doctests of the script are run, via `doctest` or `test --doc`
     ///
-    /// Follow the documentation found
-    /// [here](https://doc.rust-lang.org/cargo/reference/environment-variables.html).
+    fn is_doctest_call(call: &CargoCall, options: &ProjectOptions) -> bool {
+        let has_doc_flag = call
+            .args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--doc");
+        call.command == "test" && (options.doctest || has_doc_flag)
+    }
+
+    /// Determine the project information without modifying the file system
     ///
-    fn find_cargo_home_dir() -> Result<PathBuf> {
-        if let Some(cargo_home) = std::env::var_os("CARGO_HOME") {
-            let cargo_home = PathBuf::from(cargo_home);
-            return Ok(cargo_home);
-        }
+    fn load_project_info(target: impl AsRef<Path>, env: &impl ExecutionEnv) -> Result<ProjectInfo> {
+        let target = target.as_ref();
+        let manifest_dir = find_project_dir(target, env)?;
+        let manifest_path = manifest_dir.join("Cargo.toml");
 
-        let env_var = if std::env::consts::OS != "windows" {
-            "HOME"
-        } else {
-            "USERPROFILE"
-        };
+        let script_path = env.normalize(target)?;
+        let manifest = parse_manifest_file(target)?;
+        let options = parse_custom_section(&manifest)?;
+        let manifest = normalize_manifest(manifest, target, env)?;
+        let name = package_name(&manifest)?.to_owned();
 
-        // TODO: handle windows
-        if let Some(user_home) = std::env::var_os(env_var) {
-            let mut user_home = PathBuf::from(user_home);
-            user_home.push(".cargo");
-            return Ok(user_home);
-        }
+        return Ok(ProjectInfo {
+            script_path,
+            manifest_path,
+            manifest_dir,
+            invocation_dir: env.get_working_directory(),
+            name,
+            manifest,
+            options,
+        });
 
-        bail!("Could not determine cargo home directory");
+        fn parse_manifest_file(path: impl AsRef<Path>) -> Result<Value> {
+            let file = File::open(path)?;
+            parse_manifest(file)
+        }
     }
-}
-
-mod manifest_normalization {
-    use std::path::Path;
 
-    use anyhow::{anyhow, bail, ensure, Context, Result};
-    use toml::{value::Table, Value};
+    /// Determine the project information used to uninstall a script
+    ///
+    /// For deleted scripts only the location is known, the default options
+    /// are used.
+    ///
+    fn load_uninstall_info(
+        script_path: &Path,
+        package: &str,
+        env: &impl ExecutionEnv,
+    ) -> Result<ProjectInfo> {
+        if script_path.exists() {
+            return load_project_info(script_path, env);
+        }
 
-    use super::{execution_env::ExecutionEnv, util::to_utf8_string};
+        let manifest_dir = find_normalized_project_dir(script_path, env)?;
+        Ok(ProjectInfo {
+            name: package.to_owned(),
+            script_path: script_path.to_owned(),
+            manifest_path: manifest_dir.join("Cargo.toml"),
+            manifest_dir,
+            invocation_dir: env.get_working_directory(),
+            manifest: Value::Table(Default::default()),
+            options: ProjectOptions::default(),
+        })
+    }
 
-    /// Normalize the embedded manifest that it can be used to build the target
+    /// Determine the project information of the publishable package layout
     ///
-    /// This function inserts a package section with name, version, and edition. It
-    /// makes sure at least a single target exists (a binary as a default). For each
-    /// target it sets the correct path to the source file.
+    /// The package is placed in the `package` folder of the project directory.
     ///
-    pub fn normalize_manifest(
-        mut manifest: Value,
-        target_path: impl AsRef<Path>,
-        env: &impl ExecutionEnv,
-    ) -> Result<Value> {
-        let target_path = target_path.as_ref();
-        let target_directory = target_path
-            .parent()
-            .ok_or_else(|| anyhow!("Cannot get parent of target path"))?
-            .to_owned();
+    fn load_package_info(project_info: &ProjectInfo) -> Result<(ProjectInfo, PackageLayout)> {
+        let layout = package_manifest(project_info.manifest.clone(), &project_info.script_path)
+            .context("Cannot create a package from the script")?;
 
-        let target_name = to_utf8_string(
-            target_path
-                .file_stem()
-                .ok_or_else(|| anyhow!("Cannot build manifest for non file target"))?,
-        )?;
+        let mut package_info = project_info.clone();
+        package_info.manifest_dir = project_info.manifest_dir.join("package");
+        package_info.manifest_path = package_info.manifest_dir.join("Cargo.toml");
+        package_info.manifest = layout.manifest.clone();
 
-        let root = manifest
-            .as_table_mut()
-            .ok_or_else(|| anyhow!("Can only handle manifests that are tables"))?;
+        Ok((package_info, layout))
+    }
 
-        strip_custom_section(root);
-        ensure_valid_package(root, &target_name).context("Error while modifying package")?;
-        ensure_at_least_a_single_target(root).context("Error while ensuring a valid target")?;
+    /// Write the publishable package layout
+    ///
+    fn write_package_dir(package_info: &ProjectInfo, layout: &PackageLayout) -> Result<()> {
+        let package_dir = &package_info.manifest_dir;
+
+        // remove any stale files, cargo picks up files such as build.rs automatically
+        if package_dir.exists() {
+            for entry in fs::read_dir(package_dir)? {
+                let entry = entry?;
+                if entry.file_name() == "target" {
+                    continue;
+                }
+                if entry.file_type()?.is_dir() {
+                    fs::remove_dir_all(entry.path())?;
+                } else {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
 
-        patch_all_targets(root, target_path, &target_name, env)
-            .context("Error while patching the targets")?;
-        normalize_paths(root, &target_directory, env)
-            .context("Error while normalizing the file paths")?;
+        for (src, dst) in &layout.files {
+            let dst = package_dir.join(dst);
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(src, &dst).with_context(|| format!("Cannot copy {}", src.display()))?;
+        }
+        fs::write(
+            &package_info.manifest_path,
+            toml::to_string(&package_info.manifest)?,
+        )?;
 
-        Ok(manifest)
+        Ok(())
     }
 
-    /// Strip the custom section used to configure cargo-wop
+    /// The path of the `.crate` file written by cargo package
     ///
-    fn strip_custom_section(root: &mut toml::map::Map<String, Value>) {
-        root.remove("cargo-wop");
+    fn find_crate_file(call: &CargoCall, package_info: &ProjectInfo) -> Result<PathBuf> {
+        let name = package_name(&package_info.manifest)?;
+        let version = package_version(&package_info.manifest)?;
+
+        let mut result = find_target_dir(call, package_info);
+        result.push("package");
+        result.push(format!("{}-{}.crate", name, version));
+        Ok(result)
     }
 
-    /// Helper for normalize_manifest: Ensure the package table is correctly filled
+    /// Parse the custom section and retrieve cargo-wop configuration
     ///
-    fn ensure_valid_package(root: &mut toml::map::Map<String, Value>, name: &str) -> Result<()> {
-        if !root.contains_key("package") {
-            root.insert(String::from("package"), Value::Table(Default::default()));
+    fn parse_custom_section(manifest: &Value) -> Result<ProjectOptions> {
+        let mut res = ProjectOptions::default();
+
+        let section = unwrap_or! { manifest.get("cargo-wop"), return Ok(res) };
+
+        if let Some(filter) = section.get("filter") {
+            res.filter = parse_filter(filter)?;
         }
-        let package = root
-            .get_mut("package")
-            .unwrap()
-            .as_table_mut()
-            .ok_or_else(|| anyhow!("Invalid manifest: package is not a table"))?;
 
-        if !package.contains_key("name") {
-            package.insert(String::from("name"), Value::from(name));
+        if let Some(default_action) = section.get("default-action") {
+            let default_action =
+                unwrap_or! { default_action.as_array(), bail!("Default action must be an array") };
+            let mut converted_action = Vec::new();
+            for item in default_action {
+                let item = unwrap_or! {
+                    item.as_str(),
+                    bail!("Each entry in the default action must be a string")
+                };
+                converted_action.push(item.to_owned());
+            }
+
+            res.default_action = Some(converted_action);
         }
-        if !package.contains_key("version") {
-            package.insert(String::from("version"), Value::from("0.1.0"));
+
+        if let Some(doc_dir) = section.get("doc-dir") {
+            let doc_dir = unwrap_or! { doc_dir.as_str(), bail!("Doc dir must be a string") };
+            res.doc_dir = Some(doc_dir.to_owned());
         }
 
-        if !package.contains_key("edition") {
-            package.insert(String::from("edition"), Value::from("2018"));
+        if let Some(targets) = section.get("targets") {
+            let targets = unwrap_or! { targets.as_array(), bail!("targets must be an array") };
+            for target in targets {
+                res.targets.push(parse_build_target(target)?);
+            }
         }
 
-        Ok(())
-    }
+        if let Some(cargo) = section.get("cargo") {
+            let cargo = unwrap_or! { cargo.as_str(), bail!("cargo must be a string") };
+            res.cargo = Some(cargo.to_owned());
+        }
 
-    /// Helper for normalize manifest: Ensure at least a single target is available
-    ///
-    fn ensure_at_least_a_single_target(root: &mut toml::map::Map<String, Value>) -> Result<()> {
-        let has_single_bin = root.contains_key("bin")
-            && root
-                .get("bin")
-                .and_then(|b| b.as_array())
-                .map(|b| !b.is_empty())
-                .ok_or_else(|| anyhow!("Invalid manifest"))?;
-        let has_definition = root.contains_key("lib") || has_single_bin;
+        if let Some(profile) = section.get("profile") {
+            let profile = unwrap_or! { profile.as_str(), bail!("profile must be a string") };
+            res.profile = Some(profile.to_owned());
+        }
 
-        if has_definition {
-            return Ok(());
+        if let Some(toolchain) = section.get("toolchain") {
+            let toolchain = unwrap_or! { toolchain.as_str(), bail!("toolchain must be a string") };
+            res.toolchain = Some(toolchain.to_owned());
         }
 
-        root.insert(String::from("bin"), Value::Array(Default::default()));
+        if let Some(artifact_kind) = section.get("artifact-kind") {
+            res.artifact_kind = match artifact_kind.as_str() {
+                Some("default") => ArtifactKind::Default,
+                Some("python-extension") => ArtifactKind::PythonExtension,
+                Some("node-addon") => ArtifactKind::NodeAddon,
+                _ => bail!(
+                    "artifact-kind must be one of 'default', 'python-extension', 'node-addon'"
+                ),
+            };
+        }
 
-        let bins = root
-            .get_mut("bin")
-            .unwrap()
-            .as_array_mut()
-            .ok_or_else(|| anyhow!("Invalid manifest: bin is not an array"))?;
-        if bins.is_empty() {
-            bins.push(Value::Table(Default::default()));
+        if let Some(python) = section.get("python") {
+            let python = unwrap_or! { python.as_str(), bail!("python must be a string") };
+            res.python = Some(python.to_owned());
         }
 
-        Ok(())
-    }
+        if let Some(run_in) = section.get("run-in") {
+            res.run_in = match run_in.as_str() {
+                Some("cwd") => RunIn::Cwd,
+                Some("script-dir") => RunIn::ScriptDir,
+                _ => bail!("run-in must be one of 'cwd', 'script-dir'"),
+            };
+        }
 
-    /// Patch all available target definition
-    fn patch_all_targets(
-        root: &mut toml::map::Map<String, Value>,
-        path: &Path,
-        name: &str,
-        env: &impl ExecutionEnv,
-    ) -> Result<()> {
-        if let Some(lib) = root.get_mut("lib") {
-            patch_target(lib, path, &name, env)?;
+        if let Some(env) = section.get("env") {
+            res.env = parse_env(env, "env")?;
         }
 
-        if let Some(bins) = root.get_mut("bin") {
-            let bins = bins
-                .as_array_mut()
-                .ok_or_else(|| anyhow!("Invalid manifest: bin not an array"))?;
+        if let Some(build_env) = section.get("build-env") {
+            res.build_env = parse_env(build_env, "build-env")?;
+        }
 
-            for bin in bins {
-                patch_target(bin, path, &name, env)?;
+        if let Some(tasks) = section.get("tasks") {
+            let tasks = unwrap_or! { tasks.as_table(), bail!("tasks must be a table") };
+            for (name, task) in tasks {
+                let task = parse_task(task).with_context(|| format!("Invalid task {}", name))?;
+                res.tasks.push((name.to_owned(), task));
+            }
+            for (name, task) in &res.tasks {
+                for dependency in &task.depends {
+                    res.get_task(dependency)
+                        .with_context(|| format!("Invalid dependency of task {}", name))?;
+                }
             }
         }
 
-        Ok(())
-    }
+        if let Some(c_header) = section.get("c-header") {
+            let c_header = unwrap_or! { c_header.as_str(), bail!("c-header must be a string") };
+            res.c_header = Some(c_header.to_owned());
+        }
 
-    /// Helper for normalize manifest: patch the target definition to use the correct file path
-    fn patch_target(
-        target: &mut Value,
-        path: &Path,
-        name: &str,
-        env: &impl ExecutionEnv,
-    ) -> Result<()> {
-        let path = env.normalize(path)?;
-        let path = path
-            .to_str()
-            .ok_or_else(|| anyhow!("Cannot interpret path as UTF-8 string"))?
-            .to_owned();
+        if let Some(cbindgen) = section.get("cbindgen") {
+            res.cbindgen = parse_cbindgen_config(cbindgen)?;
+        }
 
-        let bin = target
-            .as_table_mut()
-            .ok_or_else(|| anyhow!("Cannot patch non table target"))?;
-        bin.insert(String::from("path"), Value::String(path));
+        if let Some(wasm_bindgen) = section.get("wasm-bindgen") {
+            res.wasm_bindgen = Some(parse_wasm_bindgen(wasm_bindgen)?);
+        }
 
-        if !bin.contains_key("name") {
-            bin.insert(String::from("name"), Value::String(name.to_owned()));
+        if let Some(doctest) = section.get("doctest") {
+            res.doctest = unwrap_or! { doctest.as_bool(), bail!("doctest must be a boolean") };
         }
 
-        Ok(())
-    }
+        if let Some(post_build) = section.get("post-build") {
+            let post_build =
+                unwrap_or! { post_build.as_array(), bail!("post-build must be an array") };
+            for hook in post_build {
+                res.post_build.push(parse_post_build_hook(hook)?);
+            }
+        }
 
-    /// The key path & mode for normalization
-    ///
-    /// Use an empty path to denote arrays or arbitrary children. All paths are
-    /// interpreted as relative to the project source path.
-    ///
-    const PATH_NORMALIZATION: &[&[&str]] = &[
-        &["lib", "path"],
-        &["bin", "", "path"],
-        &["example", "", "path"],
-        &["test", "", "path"],
-        &["bench", "", "path"],
-        &["package", "build"],
-        &["dependencies", "", "path"],
-        &["dev-dependencies", "", "path"],
-        &["build-dependencies", "", "path"],
-        &["patch", "", "", "path"],
-        &["target", "", "dependencies", "", "path"],
-    ];
+        if let Some(out_dir) = section.get("out-dir") {
+            let out_dir = unwrap_or! { out_dir.as_str(), bail!("Out dir must be a string") };
+            res.out_dir = Some(out_dir.to_owned());
+        }
 
-    fn normalize_paths(
-        root: &mut toml::map::Map<String, Value>,
-        project_source_path: impl AsRef<Path>,
-        env: &impl ExecutionEnv,
-    ) -> Result<()> {
-        let project_source_path = project_source_path.as_ref();
-        for path in PATH_NORMALIZATION.iter().copied() {
-            let child = match root.get_mut(path[0]) {
-                Some(child) => child,
-                None => continue,
-            };
-            _normalize_paths(child, project_source_path, env, path, 1)?;
+        if let Some(commands) = section.get("commands") {
+            let commands = unwrap_or! { commands.as_table(), bail!("Commands must be a table") };
+            for (command, spec) in commands {
+                let spec = parse_command_spec(spec)
+                    .with_context(|| format!("Invalid configuration for command {}", command))?;
+                res.commands.insert(command.to_owned(), spec);
+            }
         }
-        Ok(())
+
+        Ok(res)
     }
 
-    fn _normalize_paths(
-        current: &mut Value,
-        project_source_path: &Path,
-        env: &impl ExecutionEnv,
-        path: &[&str],
-        depth: usize,
-    ) -> Result<()> {
-        if depth + 1 == path.len() {
-            let current = match current.as_table_mut() {
-                Some(current) => current,
-                // NOTE: the containing item does not need to be table, e.g.,
-                // when the dependency is directly assigned to a version
-                None => return Ok(()),
+    fn parse_filter(filter: &Value) -> Result<Vec<(String, String)>> {
+        let filter = unwrap_or! { filter.as_table(), bail!("Filter must be table") };
+        let mut res = Vec::new();
+        for (src, dst) in filter {
+            let dst = unwrap_or! {
+                dst.as_str(),
+                bail!("Invalid destination for source {}, must be a string", src)
             };
+            res.push((src.to_owned(), dst.to_owned()));
+        }
+        Ok(res)
+    }
 
-            _normalize_table_item(current, project_source_path, env, path[depth])?;
-            return Ok(());
+    /// Parse a target, either given as a triple or as a table with triple and filter
+    ///
+    fn parse_build_target(target: &Value) -> Result<BuildTarget> {
+        if let Some(triple) = target.as_str() {
+            return Ok(BuildTarget {
+                triple: triple.to_owned(),
+                filter: Vec::new(),
+            });
         }
-        match current {
-            Value::Array(current) => {
-                // TODO: improve error message
-                ensure!(
-                    path[depth].is_empty(),
-                    "Unexpected array in path {:?}",
-                    &path[..depth + 1]
-                );
-                for item in current {
-                    _normalize_paths(item, project_source_path, env, path, depth + 1)?;
+
+        let target = unwrap_or! { target.as_table(), bail!("targets must be strings or tables") };
+        let mut triple = None;
+        let mut filter = Vec::new();
+        for (key, value) in target {
+            match key.as_str() {
+                "triple" => {
+                    triple = Some(unwrap_or! { value.as_str(), bail!("triple must be a string") });
                 }
+                "filter" => filter = parse_filter(value)?,
+                _ => bail!("Unknown key {} in target", key),
             }
-            Value::Table(current) => {
-                if path[depth].is_empty() {
-                    for (_, item) in current {
-                        _normalize_paths(item, project_source_path, env, path, depth + 1)?;
+        }
+
+        let triple = unwrap_or! { triple, bail!("targets require a triple") };
+        Ok(BuildTarget {
+            triple: triple.to_owned(),
+            filter,
+        })
+    }
+
+    fn parse_env(env: &Value, what: &str) -> Result<Vec<(String, String)>> {
+        let env = unwrap_or! { env.as_table(), bail!("{} must be a table", what) };
+        let mut res = Vec::new();
+        for (key, value) in env {
+            let value = unwrap_or! {
+                value.as_str(),
+                bail!("Invalid value for {} in {}, must be a string", key, what)
+            };
+            res.push((key.to_owned(), value.to_owned()));
+        }
+        Ok(res)
+    }
+
+    /// Parse a task with the keys run, depends and description
+    ///
+    fn parse_task(task: &Value) -> Result<Task> {
+        let task = unwrap_or! { task.as_table(), bail!("Tasks must be tables") };
+        let mut res = Task::default();
+
+        for (key, value) in task {
+            match key.as_str() {
+                "run" => {
+                    let steps = unwrap_or! { value.as_array(), bail!("run must be an array") };
+                    for step in steps {
+                        let step = parse_string_array(step, "Each step of run")?;
+                        ensure!(!step.is_empty(), "The steps of run must not be empty");
+                        res.run.push(step);
                     }
-                } else if current.contains_key(path[depth]) {
-                    _normalize_paths(
-                        &mut current[path[depth]],
-                        project_source_path,
-                        env,
-                        path,
-                        depth + 1,
-                    )?;
                 }
+                "depends" => res.depends = parse_string_array(value, "depends")?,
+                "description" => {
+                    let description =
+                        unwrap_or! { value.as_str(), bail!("description must be a string") };
+                    res.description = Some(description.to_owned());
+                }
+                _ => bail!("Unknown key {}", key),
             }
-            // TODO: improve error message
-            _ => bail!("Invalid value type"),
         }
-
-        Ok(())
+        Ok(res)
     }
 
-    fn _normalize_table_item(
-        current: &mut Table,
-        project_source_path: &Path,
-        env: &impl ExecutionEnv,
-        key: &str,
-    ) -> Result<()> {
-        if !current.contains_key(key) {
-            return Ok(());
+    fn parse_string_array(value: &Value, what: &str) -> Result<Vec<String>> {
+        let items = unwrap_or! { value.as_array(), bail!("{} must be an array of strings", what) };
+        let mut res = Vec::new();
+        for item in items {
+            let item = unwrap_or! { item.as_str(), bail!("{} must be an array of strings", what) };
+            res.push(item.to_owned());
         }
+        Ok(res)
+    }
 
-        let normed_path = current
-            .get(key)
-            .unwrap()
-            .as_str()
-            .ok_or_else(|| anyhow!("Invalid manifest: non string path"))?;
-        let normed_path = env.normalize(project_source_path.join(normed_path))?;
-
-        let normed_path = normed_path
-            .to_str()
-            .ok_or_else(|| anyhow!("Cannot interpret dependency path a string"))?;
+    /// Parse the supported subset of the cbindgen configuration
+    ///
+    fn parse_cbindgen_config(config: &Value) -> Result<HeaderConfig> {
+        let config = unwrap_or! { config.as_table(), bail!("cbindgen must be a table") };
 
-        current[key] = normed_path.into();
+        let as_string = |key: &str, value: &Value| -> Result<String> {
+            match value.as_str() {
+                Some(value) => Ok(value.to_owned()),
+                None => bail!("cbindgen.{} must be a string", key),
+            }
+        };
+        let as_bool = |key: &str, value: &Value| -> Result<bool> {
+            match value.as_bool() {
+                Some(value) => Ok(value),
+                None => bail!("cbindgen.{} must be a boolean", key),
+            }
+        };
 
-        Ok(())
+        let mut res = HeaderConfig::default();
+        for (key, value) in config {
+            match key.as_str() {
+                "language" => ensure!(value.as_str() == Some("C"), "Only C headers are supported"),
+                "header" => res.header = Some(as_string(key, value)?),
+                "trailer" => res.trailer = Some(as_string(key, value)?),
+                "autogen_warning" => res.autogen_warning = Some(as_string(key, value)?),
+                "include_guard" => res.include_guard = Some(as_string(key, value)?),
+                "after_includes" => res.after_includes = Some(as_string(key, value)?),
+                "pragma_once" => res.pragma_once = as_bool(key, value)?,
+                "no_includes" => res.no_includes = as_bool(key, value)?,
+                "cpp_compat" => res.cpp_compat = as_bool(key, value)?,
+                "documentation" => res.documentation = as_bool(key, value)?,
+                "sys_includes" => {
+                    res.sys_includes = parse_string_array(value, "cbindgen.sys_includes")?
+                }
+                "includes" => res.includes = parse_string_array(value, "cbindgen.includes")?,
+                _ => bail!("The cbindgen option {} is not supported", key),
+            }
+        }
+        Ok(res)
     }
-}
 
-mod manifest_parsing {
-    use std::io::{BufRead, BufReader, Read};
-
-    use anyhow::{bail, Result};
-    use toml::Value;
+    /// Parse the wasm-bindgen table, the target defaults to `web`
+    ///
+    fn parse_wasm_bindgen(wasm_bindgen: &Value) -> Result<WasmBindgen> {
+        let wasm_bindgen =
+            unwrap_or! { wasm_bindgen.as_table(), bail!("wasm-bindgen must be a table") };
+        for key in wasm_bindgen.keys() {
+            ensure!(key == "target", "Unknown key {} in wasm-bindgen", key);
+        }
+        let target = match wasm_bindgen.get("target") {
+            Some(target) => unwrap_or! {
+                target.as_str(),
+                bail!("The target of wasm-bindgen must be a string")
+            },
+            None => "web",
+        };
+        Ok(WasmBindgen {
+            target: target.to_owned(),
+        })
+    }
 
-    /// Parse the manifest from the initial doc comment
+    /// Parse a post-build hook, either given as a command or as a table
     ///
-    pub fn parse_manifest(reader: impl Read) -> Result<Value> {
-        let reader = BufReader::new(reader);
+    fn parse_post_build_hook(hook: &Value) -> Result<PostBuildHook> {
+        let (command, artifacts) = match hook {
+            Value::Array(_) => (hook, None),
+            Value::Table(table) => {
+                for key in table.keys() {
+                    ensure!(
+                        key == "command" || key == "artifacts",
+                        "Unknown key {} in post-build hook",
+                        key
+                    );
+                }
+                let command = unwrap_or! {
+                    table.get("command"),
+                    bail!("post-build hooks require a command")
+                };
+                let artifacts = match table.get("artifacts") {
+                    Some(artifacts) => Some(unwrap_or! {
+                        artifacts.as_str(),
+                        bail!("The artifacts of post-build hooks must be a string")
+                    }),
+                    None => None,
+                };
+                (command, artifacts)
+            }
+            _ => bail!("post-build hooks must be arrays or tables"),
+        };
 
-        let mut state = ParseState::Start;
-        let mut result = String::new();
+        let command =
+            unwrap_or! { command.as_array(), bail!("post-build commands must be arrays") };
+        let mut converted_command = Vec::new();
+        for arg in command {
+            let arg = unwrap_or! {
+                arg.as_str(),
+                bail!("Each argument of a post-build command must be a string")
+            };
+            converted_command.push(arg.to_owned());
+        }
+        ensure!(
+            !converted_command.is_empty(),
+            "post-build commands must not be empty"
+        );
 
-        for line in reader.lines() {
+        Ok(PostBuildHook {
+            command: converted_command,
+            artifacts: artifacts.map(str::to_owned),
+        })
+    }
+
+    /// Parse the configuration of a command not known to cargo-wop
+    ///
+    fn parse_command_spec(spec: &Value) -> Result<CommandSpec> {
+        let spec = unwrap_or! { spec.as_table(), bail!("Command spec must be a table") };
+        let mut res = CommandSpec::default();
+
+        for (key, value) in spec {
+            match key.as_str() {
+                "project-arg" => {
+                    res.project_arg = match value.as_str() {
+                        Some("manifest-path") => ProjectArg::ManifestPath,
+                        Some("path") => ProjectArg::Path,
+                        Some("working-directory") => ProjectArg::WorkingDirectory,
+                        _ => bail!(
+                            "project-arg must be one of 'manifest-path', 'path', 'working-directory'"
+                        ),
+                    };
+                }
+                "subcommand" => {
+                    res.subcommand =
+                        unwrap_or! { value.as_bool(), bail!("subcommand must be a bool") };
+                }
+                "split-args" => {
+                    res.split_args =
+                        unwrap_or! { value.as_bool(), bail!("split-args must be a bool") };
+                }
+                "release" => {
+                    res.release = unwrap_or! { value.as_bool(), bail!("release must be a bool") };
+                }
+                _ => bail!("Unknown key {}", key),
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Find the spec of a command, taking the project configuration into account
+    ///
+    /// The specs of the commands known to cargo-wop cannot be overwritten.
+    ///
+    fn command_spec(command: &str, options: &ProjectOptions) -> CommandSpec {
+        find_command_spec(command)
+            .or_else(|| options.commands.get(command).copied())
+            .unwrap_or_default()
+    }
+
+    /// Normalize calls to commands configured in the project
+    ///
+    /// Calls to commands known to cargo-wop are already normalized during
+    /// argument parsing.
+    ///
+    fn normalize_project_call(call: CargoCall, options: &ProjectOptions) -> Result<CargoCall> {
+        if find_command_spec(&call.command).is_some() {
+            return Ok(call);
+        }
+
+        match options.commands.get(&call.command) {
+            Some(spec) => call.normalize(spec),
+            None => Ok(call),
+        }
+    }
+
+    /// Resolve `build-PROFILE` and `run-PROFILE` for profiles declared in the manifest
+    ///
+    /// Commands configured in `[cargo-wop.commands]` take precedence. Returns
+    /// `None` if the command is to be forwarded to cargo as is. Undeclared
+    /// profiles are reported as errors, to not forward typos to cargo.
+    ///
+    fn resolve_profile_command(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+    ) -> Result<Option<Args>> {
+        let (base, profile) = unwrap_or! { split_profile_suffix(&call.command), return Ok(None) };
+        if project_info.options.commands.contains_key(&call.command) {
+            return Ok(None);
+        }
+        ensure_known_profile(profile, project_info)?;
+
+        let spec = find_profile_command_spec(base);
+        let mut profile_call = CargoCall::new(base, &call.target).with_profile(profile);
+        profile_call.options.toolchain = call.options.toolchain.clone();
+        let profile_call = profile_call.with_args(&call.args).normalize(&spec)?;
+        Ok(Some(profile_call.into_args()))
+    }
+
+    /// Ensure the profile is either builtin or declared in the manifest
+    ///
+    fn ensure_known_profile(profile: &str, project_info: &ProjectInfo) -> Result<()> {
+        let builtin = matches!(profile, "dev" | "release" | "test" | "bench");
+        let declared = project_info
+            .manifest
+            .get("profile")
+            .and_then(|profiles| profiles.get(profile))
+            .is_some();
+        ensure!(
+            builtin || declared,
+            "Unknown profile {}. Custom profiles must be declared in a [profile.{}] table",
+            profile,
+            profile
+        );
+        Ok(())
+    }
+
+    /// Add the profile selected by cargo-wop to the cargo arguments
+    ///
+    /// Custom profiles must be declared in the manifest.
+    ///
+    fn resolve_profile(mut call: CargoCall, project_info: &ProjectInfo) -> Result<CargoCall> {
+        let profile = match std::mem::take(&mut call.options.profile) {
+            Profile::Unset => return Ok(call),
+            Profile::Default => project_info
+                .options
+                .profile
+                .clone()
+                .unwrap_or_else(|| String::from("release")),
+            Profile::Named(profile) => profile,
+        };
+
+        ensure_known_profile(&profile, project_info)?;
+
+        let profile_args = if profile == "release" {
+            vec![OsString::from("--release")]
+        } else {
+            vec![OsString::from("--profile"), OsString::from(profile)]
+        };
+        let end = call
+            .args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(call.args.len());
+        call.args.splice(end..end, profile_args);
+        Ok(call)
+    }
+
+    /// The virtual environment to install the wheel into, relative to the working directory
+    ///
+    fn find_venv(call: &CargoCall, env: &impl ExecutionEnv) -> Option<PathBuf> {
+        let venv = call.options.venv.as_ref()?;
+        Some(env.get_working_directory().join(venv))
+    }
+
+    fn venv_python(venv: &Path) -> PathBuf {
+        if cfg!(windows) {
+            venv.join("Scripts").join("python.exe")
+        } else {
+            venv.join("bin").join("python")
+        }
+    }
+
+    /// The interpreter of the virtual environment, the configured or the default interpreter
+    ///
+    fn find_wheel_python(venv: Option<&Path>, options: &ProjectOptions) -> PathBuf {
+        match (venv, options.python.as_ref()) {
+            (Some(venv), _) => venv_python(venv),
+            (None, Some(python)) => PathBuf::from(python),
+            (None, None) if cfg!(windows) => PathBuf::from("python"),
+            (None, None) => PathBuf::from("python3"),
+        }
+    }
+
+    /// Determine the wheel tags and the file suffix of the extension module
+    ///
+    /// If no python tag is given, the tags are determined by executing the
+    /// interpreter.
+    ///
+    fn find_wheel_tags(call: &CargoCall, python: &Path) -> Result<(WheelTags, String)> {
+        if let Some(python_tag) = call.options.python_tag.as_ref() {
+            let tags = WheelTags::from_python_tag(python_tag);
+            return Ok((tags, host_extension_suffix().to_owned()));
+        }
+        query_python_tags(python)
+    }
+
+    /// Execute the interpreter to determine the wheel tags and the extension suffix
+    ///
+    fn query_python_tags(python: &Path) -> Result<(WheelTags, String)> {
+        let output = Command::new(python)
+            .arg("-c")
+            .arg(PYTHON_TAGS_SCRIPT)
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| {
+                format!(
+                    "Cannot execute {} to determine the wheel tags, pass --python-tag instead",
+                    python.display()
+                )
+            })?;
+        ensure!(
+            output.status.success(),
+            "Cannot determine the wheel tags with {}",
+            python.display()
+        );
+        WheelTags::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The name of the library target, i.e., the file name of its artifacts
+    ///
+    fn library_name(manifest: &Value) -> String {
+        manifest
+            .get("lib")
+            .and_then(|lib| lib.get("name"))
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+            .unwrap_or_else(|| package_name(manifest).unwrap_or_default().replace('-', "_"))
+    }
+
+    /// Build a wheel containing the extension module found in the build artifacts
+    ///
+    fn build_wheel(
+        project_info: &ProjectInfo,
+        artifacts: &[String],
+        tags: WheelTags,
+        ext_suffix: &str,
+    ) -> Result<Wheel> {
+        let module = artifacts
+            .iter()
+            .map(Path::new)
+            .find(|path| {
+                let ext = path.extension().and_then(OsStr::to_str);
+                matches!(ext, Some("so") | Some("dylib") | Some("dll"))
+            })
+            .ok_or_else(|| {
+                anyhow!("No extension module was built, the script must define a cdylib target")
+            })?;
+
+        let manifest = &project_info.manifest;
+        let package = manifest.get("package");
+        let lib_name = library_name(manifest);
+
+        Ok(Wheel {
+            name: package_name(manifest)?.to_owned(),
+            version: package_version(manifest)?.to_owned(),
+            summary: package
+                .and_then(|package| package.get("description"))
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            tags,
+            files: vec![(format!("{}{}", lib_name, ext_suffix), fs::read(module)?)],
+        })
+    }
+
+    fn build_wheel_install_call(venv: &Path, wheel_path: &Path) -> Command {
+        let mut result = Command::new(venv_python(venv));
+        result
+            .args(["-m", "pip", "install", "--force-reinstall", "--no-deps"])
+            .arg(wheel_path);
+        result
+    }
+
+    /// Determine the builds to run as (call, project info, output directory)
+    ///
+    /// With `--all-targets-triples` one build per configured target is
+    /// executed. Its artifacts are copied into a subfolder named after the
+    /// triple. The target specific filter entries are placed before the global
+    /// ones: exact names still take precedence over all patterns, but target
+    /// specific patterns are tried before the global patterns.
+    ///
+    fn find_builds(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+        out_dir: &Path,
+    ) -> Result<Vec<(CargoCall, ProjectInfo, PathBuf)>> {
+        if !call.options.all_targets_triples {
+            return Ok(vec![(
+                call.clone(),
+                project_info.clone(),
+                out_dir.to_owned(),
+            )]);
+        }
+
+        ensure!(
+            find_arg_value(&call.args, "--target").is_none(),
+            "--all-targets-triples cannot be combined with --target"
+        );
+        ensure!(
+            !project_info.options.targets.is_empty(),
+            "--all-targets-triples requires the targets to be configured"
+        );
+
+        let mut result = Vec::new();
+        for target in &project_info.options.targets {
+            let mut target_call = call.clone();
+            target_call.args.splice(
+                0..0,
+                [OsString::from("--target"), OsString::from(&target.triple)],
+            );
+
+            let mut target_info = project_info.clone();
+            target_info.options.filter = target
+                .filter
+                .iter()
+                .chain(project_info.options.filter.iter())
+                .cloned()
+                .collect();
+
+            result.push((target_call, target_info, out_dir.join(&target.triple)));
+        }
+        Ok(result)
+    }
+
+    /// Run the build and collect the generated artifacts in a single pass
+    ///
+    /// Compiler messages are rendered to stderr while cargo is running. If the
+    /// user requested JSON messages, they are passed through to stdout instead.
+    ///
+    fn execute_build_call(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+    ) -> Result<(i32, Vec<String>)> {
+        let (mut command, passthrough) = build_build_call(call, project_info)?;
+        let mut child = command.stdout(Stdio::piped()).spawn()?;
+        let stdout = unwrap_or! { child.stdout.take(), bail!("Cannot capture the cargo output") };
+
+        let artifacts = match read_build_output(stdout, passthrough, project_info) {
+            Ok(artifacts) => artifacts,
+            Err(err) => {
+                // do not leave cargo running in the background
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err);
+            }
+        };
+
+        let exit_code = child.wait()?.code().unwrap_or_default();
+        Ok((exit_code, artifacts))
+    }
+
+    /// Render the messages of a build and collect the generated artifacts
+    ///
+    fn read_build_output(
+        stdout: impl std::io::Read,
+        passthrough: bool,
+        project_info: &ProjectInfo,
+    ) -> Result<Vec<String>> {
+        let mut artifacts = Vec::new();
+        for line in BufReader::new(stdout).lines() {
             let line = line?;
-            let line_start = LineStart::from(line.as_str());
+            if passthrough {
+                println!("{}", line);
+            }
+
+            let message: JsonValue = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                Err(_) => {
+                    if !passthrough {
+                        println!("{}", line);
+                    }
+                    continue;
+                }
+            };
+            if !passthrough {
+                render_build_message(&message);
+            }
+            artifacts.extend(parse_build_message(&message, project_info)?);
+        }
+        Ok(artifacts)
+    }
+
+    /// Build the cargo call used for build, returns whether JSON output was requested
+    ///
+    fn build_build_call(call: &CargoCall, project_info: &ProjectInfo) -> Result<(Command, bool)> {
+        let message_format = match find_arg_value(&call.args, "--message-format") {
+            Some(message_format) => Some(to_utf8_string(message_format)?),
+            None => None,
+        };
+
+        if let Some(message_format) = message_format.as_deref() {
+            if message_format.starts_with("json") {
+                let command = build_cargo_call_with_args::<&str>(call, project_info, &[])?;
+                return Ok((command, true));
+            }
+        }
+
+        // render the diagnostics ourselves, cargo cannot mix human and JSON output
+        let mut rendered_format = if use_color(call) {
+            String::from("json-diagnostic-rendered-ansi")
+        } else {
+            String::from("json")
+        };
+        if message_format.as_deref() == Some("short") {
+            rendered_format.push_str(",json-diagnostic-short");
+        }
+
+        let mut call = call.clone();
+        call.args = remove_arg(&call.args, "--message-format");
+        let command = build_cargo_call_with_args(
+            &call,
+            project_info,
+            &["--message-format", &rendered_format],
+        )?;
+        Ok((command, false))
+    }
+
+    /// Whether the rendered diagnostics should contain colors
+    ///
+    /// Follows cargo: `--color` takes precedence over `CARGO_TERM_COLOR`, with
+    /// `auto` colors are only used if stderr is a terminal.
+    ///
+    fn use_color(call: &CargoCall) -> bool {
+        use std::io::IsTerminal;
+
+        let color = match find_arg_value(&call.args, "--color") {
+            Some(color) => color.to_owned(),
+            None => std::env::var_os("CARGO_TERM_COLOR").unwrap_or_default(),
+        };
+        if color == "always" {
+            true
+        } else if color == "never" {
+            false
+        } else {
+            std::io::stderr().is_terminal()
+        }
+    }
+
+    /// Print the rendered compiler messages to stderr
+    ///
+    fn render_build_message(message: &JsonValue) {
+        if message.get("reason").and_then(JsonValue::as_str) != Some("compiler-message") {
+            return;
+        }
+        let rendered = message
+            .get("message")
+            .and_then(|message| message.get("rendered"))
+            .and_then(JsonValue::as_str);
+        if let Some(rendered) = rendered {
+            eprint!("{}", rendered);
+        }
+    }
+
+    /// Remove a flag and its value from the arguments before any `--`
+    ///
+    fn remove_arg(args: &[OsString], flag: &str) -> Vec<OsString> {
+        let mut result = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                result.push(arg.clone());
+                result.extend(args.cloned());
+                break;
+            }
+            if arg == flag {
+                args.next();
+                continue;
+            }
+            let has_value = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix(flag))
+                .map(|rest| rest.starts_with('='))
+                .unwrap_or(false);
+            if !has_value {
+                result.push(arg.clone());
+            }
+        }
+        result
+    }
+
+    /// The target kinds whose artifacts are copied after a build
+    ///
+    const ARTIFACT_KINDS: &[&str] = &[
+        "bin",
+        "lib",
+        "rlib",
+        "dylib",
+        "cdylib",
+        "staticlib",
+        "proc-macro",
+    ];
+
+    /// Parse a cargo package id into (name, version)
+    ///
+    /// Supports both the package id spec format of newer cargo versions
+    /// (`path+file:///path/to/project#name@0.1.0`, the name may be omitted if
+    /// it equals the last path segment) and the older format
+    /// (`name 0.1.0 (path+file:///path/to/project)`).
+    ///
+    fn parse_package_id(package_id: &str) -> Option<(&str, &str)> {
+        if let Some((url, fragment)) = package_id.rsplit_once('#') {
+            if let Some((name, version)) = fragment.split_once('@') {
+                return Some((name, version));
+            }
+            let url = url.split('?').next()?;
+            let name = url.trim_end_matches('/').rsplit('/').next()?;
+            return Some((name, fragment));
+        }
+
+        let mut parts = package_id.split(' ');
+        let name = parts.next()?;
+        let version = parts.next()?;
+        Some((name, version))
+    }
+
+    /// Parse a single message of a cargo build step into the generated files
+    ///
+    fn parse_build_message(value: &JsonValue, project_info: &ProjectInfo) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        let reason = value
+            .get("reason")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| anyhow!("Invalid cargo output reason not a string"))?;
+
+        if reason != "compiler-artifact" {
+            return Ok(result);
+        }
+
+        let package_id = value
+            .get("package_id")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| anyhow!("Invalid compiler-artifact: package_id not a string"))?;
+
+        let version = package_version(&project_info.manifest).ok();
+        let is_project = match (parse_package_id(package_id), version) {
+            (Some((name, package_version)), Some(version)) => {
+                name == project_info.name && version == package_version
+            }
+            (Some((name, _)), None) => name == project_info.name,
+            (None, _) => false,
+        };
+        if !is_project {
+            return Ok(result);
+        }
+
+        let kinds = value
+            .get("target")
+            .and_then(|target| target.get("kind"))
+            .and_then(JsonValue::as_array)
+            .ok_or_else(|| anyhow!("Invalid compiler-artifact: target kind not an array"))?;
+        let is_copied_kind = kinds
+            .iter()
+            .filter_map(JsonValue::as_str)
+            .any(|kind| ARTIFACT_KINDS.contains(&kind));
+        if !is_copied_kind {
+            return Ok(result);
+        }
+
+        let filenames = value
+            .get("filenames")
+            .and_then(JsonValue::as_array)
+            .ok_or_else(|| anyhow!("Invalid compiler-artifact: filenames not an array"))?;
+
+        for filename in filenames {
+            let filename = filename
+                .as_str()
+                .ok_or_else(|| anyhow!("Invalid file name not a string"))?;
+            result.push(filename.to_owned());
+        }
+        Ok(result)
+    }
+
+    fn copy_build_artifacts<I, P, T>(
+        from: I,
+        to: T,
+        options: &ProjectOptions,
+        ext_suffix: Option<&str>,
+    ) -> Result<(Vec<PathBuf>, HashSet<usize>)>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let mut copied = Vec::new();
+        let mut used = HashSet::new();
+        for src in from {
+            let src = src.as_ref();
+
+            let src_file_name = unwrap_or! { src.file_name(), bail!("Invalid source filename") };
+            let (mut dst_file_name, filter_idx) = apply_filter(src_file_name, options)?;
+            used.extend(filter_idx);
+
+            if filter_idx.is_none() {
+                let kind_file_name = src_file_name.to_str().and_then(|name| {
+                    artifact_kind_file_name(options.artifact_kind, name, ext_suffix)
+                });
+                if let Some(kind_file_name) = kind_file_name {
+                    dst_file_name = OsString::from(kind_file_name);
+                }
+            }
+
+            if dst_file_name.is_empty() {
+                continue;
+            }
+
+            let dst = to.as_ref().join(dst_file_name);
+            fs::copy(src, &dst)?;
+            copied.push(dst);
+        }
+
+        Ok((copied, used))
+    }
+
+    /// Generate the C header for the exported items of the script
+    ///
+    fn write_c_header(project_info: &ProjectInfo, path: &Path) -> Result<()> {
+        let source = fs::read_to_string(&project_info.script_path)?;
+        let header = generate_header(&source, &project_info.options.cbindgen)
+            .with_context(|| format!("Cannot generate the C header {}", path.display()))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        eprintln!(":: write {}", path.display());
+        fs::write(path, header)?;
+        Ok(())
+    }
+
+    /// The target triple wasm-bindgen is applied to
+    ///
+    const WASM_BINDGEN_TRIPLE: &str = "wasm32-unknown-unknown";
+
+    /// Whether wasm-bindgen should be executed after the build
+    ///
+    fn find_wasm_bindgen<'a>(
+        call: &CargoCall,
+        options: &'a ProjectOptions,
+    ) -> Option<&'a WasmBindgen> {
+        let triple = find_arg_value(&call.args, "--target")?;
+        if triple != WASM_BINDGEN_TRIPLE {
+            return None;
+        }
+        options.wasm_bindgen.as_ref()
+    }
+
+    /// Generate the JS bindings of the wasm module into the output directory
+    ///
+    /// The version of the CLI must match the version of the `wasm-bindgen`
+    /// crate in the lock file, as the generated bindings are incompatible
+    /// otherwise.
+    ///
+    fn run_wasm_bindgen(
+        wasm_bindgen: &WasmBindgen,
+        project_info: &ProjectInfo,
+        artifacts: &[String],
+        out_dir: &Path,
+    ) -> Result<()> {
+        let module = artifacts
+            .iter()
+            .map(Path::new)
+            .find(|path| path.extension().and_then(OsStr::to_str) == Some("wasm"))
+            .ok_or_else(|| {
+                anyhow!("No wasm module was built, the script must define a cdylib target")
+            })?;
+
+        let lock_file = project_info.manifest_dir.join("Cargo.lock");
+        let lock = fs::read_to_string(&lock_file)
+            .with_context(|| format!("Cannot read {}", lock_file.display()))?;
+        let lock = toml::from_str(&lock)?;
+        let locked_versions = find_locked_versions(&lock, "wasm-bindgen");
+        ensure!(
+            !locked_versions.is_empty(),
+            "The script must depend on the wasm-bindgen crate"
+        );
+
+        let output = Command::new("wasm-bindgen")
+            .arg("--version")
+            .output()
+            .context(
+                "Cannot execute wasm-bindgen, install it with cargo install wasm-bindgen-cli",
+            )?;
+        let cli_output = String::from_utf8_lossy(&output.stdout);
+        let cli_version = unwrap_or! {
+            parse_wasm_bindgen_version(&cli_output),
+            bail!("Cannot determine the wasm-bindgen version from {:?}", cli_output.trim())
+        };
+        ensure!(
+            locked_versions.contains(&cli_version),
+            "The wasm-bindgen CLI ({}) does not match the locked crate ({}). Install a \
+            matching version with cargo install wasm-bindgen-cli --version {}",
+            cli_version,
+            locked_versions.join(", "),
+            locked_versions[0],
+        );
+
+        let mut command = build_wasm_bindgen_call(wasm_bindgen, module, out_dir, project_info);
+        eprintln!(":: {}", format_command(&command));
+        let status = command.status()?;
+        ensure!(status.success(), "Error during generating the JS bindings");
+        Ok(())
+    }
+
+    fn build_wasm_bindgen_call(
+        wasm_bindgen: &WasmBindgen,
+        module: &Path,
+        out_dir: &Path,
+        project_info: &ProjectInfo,
+    ) -> Command {
+        let mut result = Command::new("wasm-bindgen");
+        set_tool_env(&mut result, project_info);
+        result
+            .arg("--target")
+            .arg(&wasm_bindgen.target)
+            .arg("--out-dir")
+            .arg(out_dir)
+            .arg(module);
+        result
+    }
+
+    /// The versions of a package in a parsed lock file
+    ///
+    fn find_locked_versions<'a>(lock: &'a Value, name: &str) -> Vec<&'a str> {
+        let packages = unwrap_or! {
+            lock.get("package").and_then(Value::as_array),
+            return Vec::new()
+        };
+        packages
+            .iter()
+            .filter(|package| package.get("name").and_then(Value::as_str) == Some(name))
+            .filter_map(|package| package.get("version").and_then(Value::as_str))
+            .collect()
+    }
+
+    /// Parse the output of `wasm-bindgen --version`, e.g., `wasm-bindgen 0.2.92`
+    ///
+    fn parse_wasm_bindgen_version(output: &str) -> Option<&str> {
+        let mut parts = output.split_whitespace();
+        if parts.next()? != "wasm-bindgen" {
+            return None;
+        }
+        parts.next()
+    }
+
+    /// Execute the post-build hooks for the copied artifacts
+    ///
+    fn run_post_build_hooks(
+        project_info: &ProjectInfo,
+        artifacts: &[PathBuf],
+        out_dir: &Path,
+        profile: &str,
+    ) -> Result<()> {
+        for (hook, mut command) in
+            build_post_build_calls(project_info, artifacts, out_dir, profile)?
+        {
+            eprintln!(":: post-build {}", format_command(&command));
+            let status = command
+                .status()
+                .with_context(|| format!("Cannot execute post-build hook {}", hook.command[0]))?;
+            ensure!(
+                status.success(),
+                "Post-build hook {} failed with exit code {}",
+                format_command(&command),
+                status
+                    .code()
+                    .map_or(String::from("unknown"), |code| code.to_string()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Build the commands of the post-build hooks in execution order
+    ///
+    /// Hooks using `{artifact}` are executed for each matching artifact. Other
+    /// hooks are executed once, if they select artifacts only if any matches.
+    ///
+    fn build_post_build_calls<'a>(
+        project_info: &'a ProjectInfo,
+        artifacts: &[PathBuf],
+        out_dir: &Path,
+        profile: &str,
+    ) -> Result<Vec<(&'a PostBuildHook, Command)>> {
+        let mut result = Vec::new();
+        for hook in &project_info.options.post_build {
+            let mut matching = artifacts.iter().filter(|artifact| hook.matches(artifact));
+            if hook.is_per_artifact() {
+                for artifact in matching {
+                    result.push((hook, hook.build_command(Some(artifact), out_dir, profile)?));
+                }
+            } else if hook.artifacts.is_none() || matching.next().is_some() {
+                result.push((hook, hook.build_command(None, out_dir, profile)?));
+            }
+        }
+        for (_, command) in &mut result {
+            set_tool_env(command, project_info);
+        }
+        Ok(result)
+    }
+
+    /// Set the environment of the tools executed after the build
+    ///
+    /// As the script executed via run, hooks and wasm-bindgen get the location
+    /// variables and `env`, and are executed in the directory selected by
+    /// `run-in`.
+    ///
+    fn set_tool_env(command: &mut Command, project_info: &ProjectInfo) {
+        command
+            .envs(project_info.location_env())
+            .envs(project_info.expand_env(&project_info.options.env));
+        if project_info.options.run_in == RunIn::ScriptDir {
+            command.current_dir(project_info.script_dir());
+        }
+    }
+
+    /// Rename a file according to the filter, an empty name means skip the file
+    ///
+    /// Exact file names take precedence over patterns. Patterns are tried in
+    /// the order they are declared, the first matching pattern is used. Also
+    /// returns the index of the applied filter entry.
+    ///
+    fn apply_filter(
+        src_file_name: &OsStr,
+        options: &ProjectOptions,
+    ) -> Result<(OsString, Option<usize>)> {
+        let src_str =
+            unwrap_or! { src_file_name.to_str(), return Ok((src_file_name.to_owned(), None)) };
+
+        let exact = options
+            .filter
+            .iter()
+            .position(|(src, _)| !is_pattern(src) && src == src_str);
+        if let Some(idx) = exact {
+            return Ok((OsString::from(&options.filter[idx].1), Some(idx)));
+        }
+
+        for (idx, (src, dst)) in options.filter.iter().enumerate() {
+            if !is_pattern(src) {
+                continue;
+            }
+            if let Some(captures) = match_pattern(src, src_str) {
+                let dst = render_pattern(dst, &captures)?;
+                return Ok((OsString::from(dst), Some(idx)));
+            }
+        }
+        Ok((src_file_name.to_owned(), None))
+    }
+
+    /// The file name of a dynamic library according to the artifact kind
+    ///
+    /// Returns `None` for other files. The platform is derived from the
+    /// extension of the library, so cross builds are named correctly. The
+    /// extension suffix reported by the interpreter takes precedence for
+    /// Python extensions.
+    ///
+    fn artifact_kind_file_name(
+        kind: ArtifactKind,
+        file_name: &str,
+        ext_suffix: Option<&str>,
+    ) -> Option<String> {
+        let (name, python_suffix) = if let Some(name) = file_name.strip_suffix(".dll") {
+            (name, ".pyd")
+        } else if let Some(name) = file_name
+            .strip_suffix(".so")
+            .or_else(|| file_name.strip_suffix(".dylib"))
+        {
+            (name.strip_prefix("lib")?, ".so")
+        } else {
+            return None;
+        };
+
+        match kind {
+            ArtifactKind::Default => None,
+            ArtifactKind::PythonExtension => {
+                Some(format!("{}{}", name, ext_suffix.unwrap_or(python_suffix)))
+            }
+            ArtifactKind::NodeAddon => Some(format!("{}.node", name)),
+        }
+    }
+
+    /// Query the extension suffix of the configured interpreter
+    ///
+    /// Without a configured interpreter, the suffix is derived from the
+    /// platform of the build artifacts.
+    ///
+    fn find_extension_suffix(options: &ProjectOptions) -> Result<Option<String>> {
+        if options.artifact_kind != ArtifactKind::PythonExtension {
+            return Ok(None);
+        }
+        let python = unwrap_or! { options.python.as_ref(), return Ok(None) };
+        let (_, ext_suffix) = query_python_tags(Path::new(python))?;
+        Ok(Some(ext_suffix))
+    }
+
+    /// Ensure all filter entries were applied by the builds
+    ///
+    /// The global entries are shared by all builds and only need to be applied
+    /// by one of them.
+    ///
+    fn check_strict_filter(builds: &[FilterUsage]) -> Result<()> {
+        let mut unused = Vec::new();
+        let mut used_global = HashSet::new();
+        for build in builds {
+            let target_entries = build.filter.iter().enumerate().take(build.target_filter);
+            for (idx, (src, _)) in target_entries {
+                if !build.used.contains(&idx) {
+                    unused.push(src.as_str());
+                }
+            }
+            let target_filter = build.target_filter;
+            used_global.extend(
+                build
+                    .used
+                    .iter()
+                    .filter_map(|idx| idx.checked_sub(target_filter)),
+            );
+        }
+        if let Some(build) = builds.first() {
+            for (idx, (src, _)) in build.filter[build.target_filter..].iter().enumerate() {
+                if !used_global.contains(&idx) {
+                    unused.push(src.as_str());
+                }
+            }
+        }
+        ensure!(
+            unused.is_empty(),
+            "The following filters did not match any artifact: {}",
+            unused.join(", ")
+        );
+        Ok(())
+    }
+
+    /// Determine the directory to copy the build artifacts to
+    ///
+    /// The command line argument is interpreted relative to the working
+    /// directory, the configuration relative to the script. Without either the
+    /// artifacts are copied into the working directory. The placeholder
+    /// `{profile}` is replaced by the profile of the build.
+    ///
+    fn find_out_dir(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+        env: &impl ExecutionEnv,
+    ) -> Result<PathBuf> {
+        let working_directory = env.get_working_directory();
+        let (base_dir, out_dir) = if let Some(out_dir) = call.options.out_dir.as_ref() {
+            let out_dir = to_utf8_string(out_dir.as_os_str())?;
+            (working_directory, out_dir)
+        } else if let Some(out_dir) = project_info.options.out_dir.as_ref() {
+            let script_dir = unwrap_or! {
+                project_info.script_path.parent(),
+                bail!("Cannot determine the script directory")
+            };
+            (script_dir.to_owned(), out_dir.to_owned())
+        } else {
+            return Ok(working_directory);
+        };
+
+        let out_dir = out_dir.replace("{profile}", &find_build_profile(call)?);
+        Ok(base_dir.join(out_dir))
+    }
+
+    /// The profile used by a build call, as named by cargo's output directories
+    ///
+    fn find_build_profile(call: &CargoCall) -> Result<String> {
+        if let Some(profile) = find_arg_value(&call.args, "--profile") {
+            // the builtin profiles share the directories of dev and release
+            return match to_utf8_string(profile)?.as_str() {
+                "dev" | "test" => Ok(String::from("debug")),
+                "release" | "bench" => Ok(String::from("release")),
+                profile => Ok(profile.to_owned()),
+            };
+        }
+        let release = call
+            .args
+            .iter()
+            .take_while(|arg| *arg != "--")
+            .any(|arg| arg == "--release");
+        Ok(String::from(if release { "release" } else { "debug" }))
+    }
+
+    /// Determine the directory to copy the documentation to, if any
+    ///
+    /// The command line argument is interpreted relative to the working
+    /// directory, the configuration relative to the script.
+    ///
+    fn find_doc_dir(
+        call: &CargoCall,
+        project_info: &ProjectInfo,
+        env: &impl ExecutionEnv,
+    ) -> Result<Option<PathBuf>> {
+        if let Some(doc_dir) = call.options.doc_dir.as_ref() {
+            return Ok(Some(env.get_working_directory().join(doc_dir)));
+        }
+
+        let doc_dir = unwrap_or! { project_info.options.doc_dir.as_ref(), return Ok(None) };
+        let script_dir = unwrap_or! {
+            project_info.script_path.parent(),
+            bail!("Cannot determine the script directory")
+        };
+        Ok(Some(script_dir.join(doc_dir)))
+    }
+
+    /// The directory cargo doc writes its output to
+    ///
+    fn find_doc_output_dir(call: &CargoCall, project_info: &ProjectInfo) -> PathBuf {
+        let mut result = find_target_dir(call, project_info);
+        if let Some(triple) = find_arg_value(&call.args, "--target") {
+            result.push(triple);
+        }
+        result.push("doc");
+        result
+    }
+
+    /// The target directory used by cargo
+    ///
+    fn find_target_dir(call: &CargoCall, project_info: &ProjectInfo) -> PathBuf {
+        if let Some(target_dir) = find_arg_value(&call.args, "--target-dir") {
+            PathBuf::from(target_dir)
+        } else if let Some(target_dir) = std::env::var_os("CARGO_TARGET_DIR") {
+            PathBuf::from(target_dir)
+        } else {
+            project_info.manifest_dir.join("target")
+        }
+    }
+
+    /// Copy the generated documentation, applying the filter to its entries
+    ///
+    fn copy_documentation(from: &Path, to: &Path, options: &ProjectOptions) -> Result<()> {
+        ensure!(
+            from.is_dir(),
+            "Could not find the generated documentation in {}",
+            from.display()
+        );
+        fs::create_dir_all(to)?;
+
+        for entry in fs::read_dir(from)? {
+            let src = entry?.path();
+            let src_file_name = unwrap_or! { src.file_name(), bail!("Invalid source filename") };
+            let (dst_file_name, _) = apply_filter(src_file_name, options)?;
+
+            if dst_file_name.is_empty() {
+                continue;
+            }
+            super::util::copy_all(&src, to.join(dst_file_name))?;
+        }
+        Ok(())
+    }
+
+    /// Find the value of an argument given as `--flag value` or `--flag=value`
+    ///
+    fn find_arg_value<'a>(args: &'a [OsString], flag: &str) -> Option<&'a OsStr> {
+        let mut args = args.iter().take_while(|arg| *arg != "--");
+        while let Some(arg) = args.next() {
+            if arg == flag {
+                return args.next().map(OsString::as_os_str);
+            }
+
+            let value = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix(flag))
+                .and_then(|arg| arg.strip_prefix('='));
+            if let Some(value) = value {
+                return Some(OsStr::new(value));
+            }
+        }
+        None
+    }
+
+    impl ProjectInfo {
+        fn script_dir(&self) -> &Path {
+            self.script_path.parent().unwrap_or_else(|| Path::new(""))
+        }
+
+        /// The variables describing the location of the script set for every cargo call
+        ///
+        fn location_env(&self) -> Vec<(&'static str, &OsStr)> {
+            vec![
+                ("CARGO_WOP_SCRIPT_PATH", self.script_path.as_os_str()),
+                ("CARGO_WOP_SCRIPT_DIR", self.script_dir().as_os_str()),
+                ("CARGO_WOP_PROJECT_DIR", self.manifest_dir.as_os_str()),
+                ("CARGO_WOP_INVOCATION_DIR", self.invocation_dir.as_os_str()),
+            ]
+        }
+
+        /// Expand the `{script_dir}` and `{project_dir}` placeholders in the values
+        ///
+        fn expand_env(&self, env: &[(String, String)]) -> Vec<(String, String)> {
+            let script_dir = self.script_dir();
+            env.iter()
+                .map(|(key, value)| {
+                    let value = value
+                        .replace("{script_dir}", &script_dir.to_string_lossy())
+                        .replace("{project_dir}", &self.manifest_dir.to_string_lossy());
+                    (key.to_owned(), value)
+                })
+                .collect()
+        }
+    }
+
+    #[derive(Clone)]
+    struct ProjectInfo {
+        name: String,
+        /// The absolute path of the script
+        script_path: PathBuf,
+        manifest_path: PathBuf,
+        manifest_dir: PathBuf,
+        /// The working directory cargo-wop was called in
+        invocation_dir: PathBuf,
+        /// The normalized manifest
+        manifest: Value,
+        options: ProjectOptions,
+    }
+
+    #[derive(Default, Debug, Clone)]
+    struct ProjectOptions {
+        /// Rename or skip build artifacts, the entries in declaration order.
+        /// Exact file names take precedence over patterns.
+        filter: Vec<(String, String)>,
+        default_action: Option<Vec<String>>,
+        /// The argument handling of additional cargo commands
+        commands: HashMap<String, CommandSpec>,
+        /// The directory to copy the documentation into, relative to the script
+        doc_dir: Option<String>,
+        /// The directory to copy the build artifacts into, relative to the script
+        out_dir: Option<String>,
+        /// Commands executed after the build artifacts were copied
+        post_build: Vec<PostBuildHook>,
+        /// Named sequences of cargo wop commands, in declaration order
+        tasks: Vec<(String, Task)>,
+        /// Environment variables set for cargo and post-build hooks
+        env: Vec<(String, String)>,
+        /// Environment variables set for cargo but not for hooks, e.g.,
+        /// `RUSTFLAGS`. Programs executed by cargo, e.g., via run, inherit them.
+        build_env: Vec<(String, String)>,
+        /// The working directory of the script executed via run and of post-build hooks
+        run_in: RunIn,
+        /// The rustup toolchain used for all cargo calls
+        toolchain: Option<String>,
+        /// The profile used by build and run instead of release
+        profile: Option<String>,
+        /// The cargo binary to use, e.g., `cross`
+        cargo: Option<String>,
+        /// The target triples built with `--all-targets-triples`
+        targets: Vec<BuildTarget>,
+        /// How dynamic libraries are named if no filter entry applies
+        artifact_kind: ArtifactKind,
+        /// The python interpreter used to determine the extension suffix
+        python: Option<String>,
+        /// Generate JS bindings for wasm32 builds
+        wasm_bindgen: Option<WasmBindgen>,
+        /// The C header to generate next to the artifacts, relative to the output directory
+        c_header: Option<String>,
+        /// The configuration of the C header, using the keys of `cbindgen.toml`
+        cbindgen: HeaderConfig,
+        /// Run the doctests of the script with `test`
+        doctest: bool,
+    }
+
+    /// A target triple with additional filter entries
+    ///
+    #[derive(Debug, Clone, PartialEq)]
+    struct BuildTarget {
+        triple: String,
+        filter: Vec<(String, String)>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    enum RunIn {
+        /// The directory cargo-wop is called in
+        #[default]
+        Cwd,
+        /// The directory containing the script
+        ScriptDir,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    enum ArtifactKind {
+        /// Keep the names generated by cargo
+        #[default]
+        Default,
+        /// Name dynamic libraries as importable Python extension modules
+        PythonExtension,
+        /// Name dynamic libraries as Node.js addons
+        NodeAddon,
+    }
+
+    impl ProjectOptions {
+        fn get_task(&self, name: &str) -> Result<&Task> {
+            match self.tasks.iter().find(|(task, _)| task == name) {
+                Some((_, task)) => Ok(task),
+                None => bail!(
+                    "Unknown task {}. Use 'cargo wop tasks' to list the available tasks",
+                    name
+                ),
+            }
+        }
+    }
+
+    /// A task configured in the manifest
+    ///
+    #[derive(Debug, Default, Clone, PartialEq)]
+    struct Task {
+        /// The cargo wop commands to run, each given as command and arguments
+        run: Vec<Vec<String>>,
+        /// The tasks to run before this task
+        depends: Vec<String>,
+        description: Option<String>,
+    }
+
+    /// The options of the wasm-bindgen CLI run after wasm32 builds
+    ///
+    #[derive(Debug, Clone, PartialEq)]
+    struct WasmBindgen {
+        /// The kind of the generated bindings, e.g., `web` or `nodejs`
+        target: String,
+    }
+
+    /// A command executed after the build artifacts were copied
+    ///
+    /// Hooks that use the `{artifact}` placeholder are executed once for each
+    /// copied artifact matching the pattern, all others once per build.
+    ///
+    #[derive(Debug, Clone, PartialEq)]
+    struct PostBuildHook {
+        command: Vec<String>,
+        artifacts: Option<String>,
+    }
+
+    impl PostBuildHook {
+        fn is_per_artifact(&self) -> bool {
+            self.command.iter().any(|arg| arg.contains("{artifact}"))
+        }
+
+        fn matches(&self, artifact: &Path) -> bool {
+            let pattern = unwrap_or! { self.artifacts.as_ref(), return true };
+            let file_name = artifact.file_name().and_then(OsStr::to_str);
+            file_name.is_some_and(|file_name| match_pattern(pattern, file_name).is_some())
+        }
+
+        /// Build the command replacing the placeholders
+        fn build_command(
+            &self,
+            artifact: Option<&Path>,
+            out_dir: &Path,
+            profile: &str,
+        ) -> Result<Command> {
+            let mut args = self.command.iter().map(|arg| {
+                let mut arg = arg
+                    .replace("{out_dir}", &out_dir.to_string_lossy())
+                    .replace("{profile}", profile);
+                if let Some(artifact) = artifact {
+                    arg = arg.replace("{artifact}", &artifact.to_string_lossy());
+                }
+                arg
+            });
+
+            let program = unwrap_or! { args.next(), bail!("Post-build hooks must not be empty") };
+            let mut result = Command::new(program);
+            result.args(args);
+            Ok(result)
+        }
+    }
+
+    /// Find the project directory from the supplied file
+    ///
+    fn find_project_dir(source: impl AsRef<Path>, env: &impl ExecutionEnv) -> Result<PathBuf> {
+        let source = env.normalize(source.as_ref())?;
+        find_normalized_project_dir(&source, env)
+    }
+
+    /// Find the project directory of an already normalized script path
+    ///
+    fn find_normalized_project_dir(source: &Path, env: &impl ExecutionEnv) -> Result<PathBuf> {
+        let target_name = source
+            .file_stem()
+            .ok_or_else(|| anyhow!("Could not get path stem"))?;
+        let mut target_name = target_name.to_owned();
+        target_name.push("-");
+        target_name.push(hash_path(source));
+
+        let mut result = find_cache_dir(env)?;
+        result.push(target_name);
+
+        Ok(result)
+    }
+
+    /// Find the internal cache dir for cargo-wop
+    ///
+    fn find_cache_dir(env: &impl ExecutionEnv) -> Result<PathBuf> {
+        let mut result = env.get_cargo_home_dir();
+        result.push("wop-cache");
+        Ok(result)
+    }
+
+    fn hash_path(path: impl AsRef<Path>) -> String {
+        let mut hash = Sha1::new();
+        hash.update(path.as_ref().to_string_lossy().as_bytes());
+        let digest = hash.digest();
+        let res = digest.to_string();
+        res[..8].to_string()
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        // wrapper to simplify test code
+        fn merge_default_args(call: DefaultAction, default_action: Option<&[&str]>) -> Vec<String> {
+            let default_action = default_action.map(to_strings);
+            super::merge_default_args(&call, &default_action)
+                .iter()
+                .map(|s| OsString::into_string(s.clone()).unwrap())
+                .collect()
+        }
+
+        fn to_strings(data: &[&str]) -> Vec<String> {
+            data.iter().map(|s| (*s).to_owned()).collect()
+        }
+
+        #[test]
+        fn test_command_specs() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop.commands]
+                my-tool = { project-arg = "working-directory", split-args = true }
+                run = { release = false }
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+
+            let spec = command_spec("my-tool", &options);
+            assert_eq!(spec.project_arg, ProjectArg::WorkingDirectory);
+            assert!(spec.split_args);
+            assert!(!spec.release);
+
+            // builtin commands cannot be overwritten
+            assert!(command_spec("run", &options).release);
+            assert_eq!(command_spec("unknown", &options), CommandSpec::default());
+
+            let call = normalize_project_call(
+                CargoCall::new("my-tool", "foo.rs").with_args(["--flag", "arg"]),
+                &options,
+            )?;
+            assert_eq!(
+                call.args,
+                vec![OsString::from("--"), "--flag".into(), "arg".into()]
+            );
+
+            let invalid: Value =
+                toml::from_str("[cargo-wop.commands]\nmy-tool = { project-arg = \"foo\" }")?;
+            assert!(parse_custom_section(&invalid).is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_find_arg_value() {
+            let args = [
+                "--target",
+                "wasm32-unknown-unknown",
+                "--target-dir=foo",
+                "--",
+                "--bar",
+            ]
+            .iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+
+            assert_eq!(
+                find_arg_value(&args, "--target"),
+                Some(OsStr::new("wasm32-unknown-unknown"))
+            );
+            assert_eq!(
+                find_arg_value(&args, "--target-dir"),
+                Some(OsStr::new("foo"))
+            );
+            assert_eq!(find_arg_value(&args, "--bar"), None);
+        }
+
+        #[test]
+        fn test_parse_package_id() {
+            assert_eq!(
+                parse_package_id("example 0.1.0 (path+file:///tmp/example-1234)"),
+                Some(("example", "0.1.0"))
+            );
+            assert_eq!(
+                parse_package_id("path+file:///tmp/example-1234#example@0.1.0"),
+                Some(("example", "0.1.0"))
+            );
+            assert_eq!(
+                parse_package_id("path+file:///tmp/example#0.1.0"),
+                Some(("example", "0.1.0"))
+            );
+            assert_eq!(
+                parse_package_id(
+                    "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"
+                ),
+                Some(("serde", "1.0.0"))
+            );
+        }
+
+        /// A project for the script /scripts/example.rs with the given manifest
+        fn example_project_info(manifest: &Value) -> Result<ProjectInfo> {
+            Ok(ProjectInfo {
+                name: String::from("example"),
+                script_path: PathBuf::from("/scripts/example.rs"),
+                manifest_path: PathBuf::from("/cache/example/Cargo.toml"),
+                manifest_dir: PathBuf::from("/cache/example"),
+                invocation_dir: PathBuf::from("/work"),
+                manifest: Value::Table(Default::default()),
+                options: parse_custom_section(manifest)?,
+            })
+        }
+
+        #[test]
+        fn test_find_builds() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                filter = { "*.d" = "" }
+                targets = [
+                    "x86_64-unknown-linux-gnu",
+                    { triple = "wasm32-unknown-unknown", filter = { "*.wasm" = "app.wasm" } },
+                ]
+                "#,
+            )?;
+            let project_info = example_project_info(&manifest)?;
+            let call = CargoCall::new("build", "example.rs").with_args(["--release"]);
+
+            let builds = find_builds(&call, &project_info, Path::new("out"))?;
+            assert_eq!(builds.len(), 1);
+            assert_eq!(builds[0].0, call);
+            assert_eq!(builds[0].2, Path::new("out"));
+
+            let mut all_call = call.clone();
+            all_call.options.all_targets_triples = true;
+            let builds = find_builds(&all_call, &project_info, Path::new("out"))?;
+            assert_eq!(builds.len(), 2);
+
+            let (wasm_call, wasm_info, wasm_out_dir) = &builds[1];
+            assert_eq!(
+                find_arg_value(&wasm_call.args, "--target"),
+                Some(OsStr::new("wasm32-unknown-unknown"))
+            );
+            assert_eq!(
+                wasm_out_dir,
+                &Path::new("out").join("wasm32-unknown-unknown")
+            );
+            assert_eq!(
+                wasm_info.options.filter,
+                vec![
+                    (String::from("*.wasm"), String::from("app.wasm")),
+                    (String::from("*.d"), String::from("")),
+                ]
+            );
+
+            let mut target_call = all_call.with_args(["--target", "aarch64-apple-darwin"]);
+            assert!(find_builds(&target_call, &project_info, Path::new("out")).is_err());
+
+            target_call.args.clear();
+            let no_targets = example_project_info(&toml::from_str("")?)?;
+            assert!(find_builds(&target_call, &no_targets, Path::new("out")).is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_env() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                env = { DATA = "{script_dir}/data", CACHE = "{project_dir}/cache" }
+                build-env = { RUSTFLAGS = "-C target-cpu=native" }
+                "#,
+            )?;
+            let project_info = example_project_info(&manifest)?;
+
+            assert_eq!(
+                project_info.expand_env(&project_info.options.env),
+                vec![
+                    (String::from("DATA"), String::from("/scripts/data")),
+                    (String::from("CACHE"), String::from("/cache/example/cache")),
+                ]
+            );
+            assert_eq!(
+                project_info.options.build_env,
+                vec![(
+                    String::from("RUSTFLAGS"),
+                    String::from("-C target-cpu=native")
+                )]
+            );
+
+            let invalid: Value = toml::from_str("[cargo-wop]\nenv = { DEBUG = 1 }")?;
+            assert!(parse_custom_section(&invalid).is_err());
+
+            let command = build_cargo_call_with_args::<&str>(
+                &CargoCall::new("check", "example.rs"),
+                &project_info,
+                &[],
+            )?;
+            let envs = command.get_envs().collect::<Vec<_>>();
+            assert!(envs.contains(&(
+                OsStr::new("CARGO_WOP_SCRIPT_DIR"),
+                Some(OsStr::new("/scripts"))
+            )));
+            assert!(envs.contains(&(
+                OsStr::new("CARGO_WOP_INVOCATION_DIR"),
+                Some(OsStr::new("/work"))
+            )));
+            Ok(())
+        }
+
+        #[test]
+        fn test_select_cargo() {
+            let select = |wop_cargo: Option<&str>, configured, cargo: Option<&str>, toolchain| {
+                select_cargo(
+                    wop_cargo.map(OsString::from),
+                    configured,
+                    cargo.map(OsString::from),
+                    toolchain,
+                )
+            };
+
+            assert_eq!(select(None, None, None, false), "cargo");
+            assert_eq!(select(None, None, Some("/bin/cargo"), false), "/bin/cargo");
+            assert_eq!(select(None, None, Some("/bin/cargo"), true), "cargo");
+            assert_eq!(
+                select(None, Some("cross"), Some("/bin/cargo"), false),
+                "cross"
+            );
+            assert_eq!(
+                select(Some("wrapper"), Some("cross"), None, false),
+                "wrapper"
+            );
+        }
+
+        #[test]
+        fn test_run_in() -> Result<()> {
+            let manifest: Value = toml::from_str("[cargo-wop]\nrun-in = \"script-dir\"")?;
+            let mut project_info = example_project_info(&manifest)?;
+
+            let run = CargoCall::new("run", "example.rs");
+            let command = build_cargo_call_with_args::<&str>(&run, &project_info, &[])?;
+            assert_eq!(command.get_current_dir(), Some(Path::new("/scripts")));
+
+            let check = CargoCall::new("check", "example.rs");
+            let command = build_cargo_call_with_args::<&str>(&check, &project_info, &[])?;
+            assert_eq!(command.get_current_dir(), None);
+
+            project_info.options.run_in = RunIn::Cwd;
+            let command = build_cargo_call_with_args::<&str>(&run, &project_info, &[])?;
+            assert_eq!(command.get_current_dir(), None);
+
+            let invalid: Value = toml::from_str("[cargo-wop]\nrun-in = \"manifest\"")?;
+            assert!(parse_custom_section(&invalid).is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_c_header_config() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                c-header = "include/example.h"
+
+                [cargo-wop.cbindgen]
+                language = "C"
+                include_guard = "EXAMPLE_H"
+                sys_includes = ["stdio.h"]
+                cpp_compat = true
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+            assert_eq!(options.c_header.as_deref(), Some("include/example.h"));
+            assert_eq!(
+                options.cbindgen,
+                HeaderConfig {
+                    include_guard: Some(String::from("EXAMPLE_H")),
+                    sys_includes: vec![String::from("stdio.h")],
+                    cpp_compat: true,
+                    ..HeaderConfig::default()
+                }
+            );
+
+            for invalid in ["language = \"C++\"", "export = {}", "cpp_compat = 1"] {
+                let invalid: Value = toml::from_str(&format!("[cargo-wop.cbindgen]\n{}", invalid))?;
+                assert!(parse_custom_section(&invalid).is_err());
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn test_doctest() -> Result<()> {
+            let manifest: Value = toml::from_str("[cargo-wop]\ndoctest = true")?;
+            let options = parse_custom_section(&manifest)?;
+            assert!(options.doctest);
+            assert!(is_doctest_call(
+                &CargoCall::new("test", "example.rs"),
+                &options
+            ));
+            assert!(!is_doctest_call(
+                &CargoCall::new("build", "example.rs"),
+                &options
+            ));
+
+            let options = ProjectOptions::default();
+            let call = CargoCall::new("test", "example.rs").with_args(["--doc"]);
+            assert!(is_doctest_call(&call, &options));
+            let call = CargoCall::new("test", "example.rs").with_args(["--", "--doc"]);
+            assert!(!is_doctest_call(&call, &options));
+
+            let mut manifest: Value = toml::from_str(
+                "[package]\nname = \"example\"\n\n[[bin]]\npath = \"/scripts/example.rs\"",
+            )?;
+            patch_doctest_target(&mut manifest, Path::new("/scripts/example.rs"))?;
+            let expected: Value = toml::from_str(
+                r#"
+                [package]
+                name = "example"
+
+                [[bin]]
+                path = "/scripts/example.rs"
+
+                [lib]
+                name = "example"
+                path = "/scripts/example.rs"
+                crate-type = ["rlib"]
+                test = false
+                bench = false
+                doc = false
+                doctest = true
+
+                [lints.rust]
+                dead_code = "allow"
+                "#,
+            )?;
+            assert_eq!(manifest, expected);
+
+            // existing libraries are also built as rlibs
+            let mut manifest: Value = toml::from_str("[lib]\ncrate-type = [\"cdylib\"]")?;
+            patch_doctest_target(&mut manifest, Path::new("/scripts/example.rs"))?;
+            let expected: Value = toml::from_str("[lib]\ncrate-type = [\"cdylib\", \"rlib\"]")?;
+            assert_eq!(manifest, expected);
+
+            let invalid: Value = toml::from_str("[cargo-wop]\ndoctest = \"yes\"")?;
+            assert!(parse_custom_section(&invalid).is_err());
+            Ok(())
+        }
+
+        /// An environment rooted in a temporary directory
+        #[derive(Clone)]
+        struct TempEnv(PathBuf);
+
+        impl ExecutionEnv for TempEnv {
+            fn get_cargo_home_dir(&self) -> PathBuf {
+                self.0.join("cargo-home")
+            }
+
+            fn get_working_directory(&self) -> PathBuf {
+                self.0.clone()
+            }
+
+            fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+                Ok(self.0.join(path))
+            }
+        }
+
+        #[test]
+        fn test_find_installed_package() -> Result<()> {
+            let dir = std::env::temp_dir().join(format!(
+                "wop-test-installed-{}-{}",
+                std::process::id(),
+                hash_path("test_find_installed_package"),
+            ));
+            fs::create_dir_all(dir.join("scripts"))?;
+            let env = TempEnv(dir.clone());
+
+            let script = dir.join("scripts").join("example.rs");
+            let deleted = dir.join("scripts").join("deleted.rs");
+            fs::write(
+                &script,
+                concat!(
+                    "//! ```cargo\n//! [package]\n//! name = \"renamed\"\n",
+                    "//! [cargo-wop]\n//! cargo = \"my-cargo\"\n//! ```\n",
+                ),
+            )?;
+            fs::write(&deleted, "fn main() {}")?;
+            update_install_records(&env, |records| {
+                records.insert(InstallRecord::new(&script, "example", Vec::new()).unwrap());
+                records.insert(InstallRecord::new(&deleted, "deleted", Vec::new()).unwrap());
+            })?;
+            fs::remove_file(&deleted)?;
+
+            assert_eq!(
+                find_installed_package(Path::new("scripts/example.rs"), &env)?,
+                (script.clone(), String::from("example")),
+            );
+            assert_eq!(
+                find_installed_package(Path::new("scripts/../scripts/./deleted.rs"), &env)?,
+                (deleted.clone(), String::from("deleted")),
+            );
+            assert!(find_installed_package(Path::new("scripts/unknown.rs"), &env).is_err());
+
+            // uninstall calls get the location variables, also for deleted scripts
+            for (script, package) in [(&script, "example"), (&deleted, "deleted")] {
+                let project_info = load_uninstall_info(script, package, &env)?;
+                let command = build_uninstall_call(
+                    &CargoCall::new("uninstall", script),
+                    package,
+                    &project_info,
+                )?;
+                assert!(command.get_envs().any(|(key, value)| {
+                    key == "CARGO_WOP_SCRIPT_PATH" && value == Some(script.as_os_str())
+                }));
+            }
+
+            // the configured cargo binary is used as for install
+            if std::env::var_os("CARGO_WOP_CARGO").is_none() {
+                let project_info = load_uninstall_info(&script, "example", &env)?;
+                let call = CargoCall::new("uninstall", &script);
+                let command = build_uninstall_call(&call, "example", &project_info)?;
+                assert_eq!(command.get_program(), "my-cargo");
+            }
+
+            fs::remove_dir_all(&dir)?;
+            Ok(())
+        }
+
+        #[test]
+        fn test_normalize_path() {
+            assert_eq!(
+                normalize_path(Path::new("/scripts/./a/../b.rs")),
+                PathBuf::from("/scripts/b.rs")
+            );
+            assert_eq!(
+                normalize_path(Path::new("/scripts/../../b.rs")),
+                PathBuf::from("/b.rs")
+            );
+        }
+
+        /// Run the doc example of a binary script with warnings denied
+        ///
+        /// Requires a working toolchain, run it via `cargo test -- --ignored`.
+        #[test]
+        #[ignore]
+        fn test_doctest_of_bin_script() -> Result<()> {
+            let dir = std::env::temp_dir().join(format!(
+                "wop-test-doctest-{}-{}",
+                std::process::id(),
+                hash_path("test_doctest_of_bin_script"),
+            ));
+            fs::create_dir_all(&dir)?;
+            fs::write(
+                dir.join("my-script.rs"),
+                concat!(
+                    "//! ```cargo\n",
+                    "//! [cargo-wop]\n",
+                    "//! build-env = { RUSTFLAGS = \"-D warnings\" }\n",
+                    "//! ```\n",
+                    "\n",
+                    "/// ```\n",
+                    "/// assert_eq!(my_script::add(1, 2), 3);\n",
+                    "/// ```\n",
+                    "pub fn add(a: i32, b: i32) -> i32 {\n",
+                    "    a + b\n",
+                    "}\n",
+                    "\n",
+                    "fn helper() -> i32 {\n",
+                    "    1\n",
+                    "}\n",
+                    "\n",
+                    "fn main() {\n",
+                    "    println!(\"{}\", add(helper(), 2));\n",
+                    "}\n",
+                ),
+            )?;
+
+            let env = TempEnv(dir.clone());
+            let script = dir.join("my-script.rs");
+            let args = ["wop", "test", script.to_str().unwrap(), "--doc"];
+            let args = crate::parse_args(args.iter().map(OsString::from))?;
+            let result = execute_args(args, &env);
+            fs::remove_dir_all(&dir)?;
+            assert_eq!(result?, 0);
+            Ok(())
+        }
+
+        #[test]
+        fn test_wasm_bindgen() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                wasm-bindgen = { target = "nodejs" }
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+            assert_eq!(
+                options.wasm_bindgen,
+                Some(WasmBindgen {
+                    target: String::from("nodejs")
+                })
+            );
+
+            let call = CargoCall::new("build", "example.rs");
+            assert_eq!(find_wasm_bindgen(&call, &options), None);
+            let call = call.with_args(["--target", "wasm32-unknown-unknown"]);
+            assert!(find_wasm_bindgen(&call, &options).is_some());
+
+            let default: Value = toml::from_str("[cargo-wop]\nwasm-bindgen = {}")?;
+            let default = parse_custom_section(&default)?.wasm_bindgen;
+            assert_eq!(default.map(|opts| opts.target).as_deref(), Some("web"));
+
+            let invalid: Value = toml::from_str("[cargo-wop]\nwasm-bindgen = { out = \"js\" }")?;
+            assert!(parse_custom_section(&invalid).is_err());
+
+            let lock: Value = toml::from_str(
+                r#"
+                [[package]]
+                name = "wasm-bindgen"
+                version = "0.2.92"
+
+                [[package]]
+                name = "wasm-bindgen-macro"
+                version = "0.2.92"
+                "#,
+            )?;
+            assert_eq!(find_locked_versions(&lock, "wasm-bindgen"), vec!["0.2.92"]);
+            assert!(find_locked_versions(&lock, "serde").is_empty());
+
+            assert_eq!(
+                parse_wasm_bindgen_version("wasm-bindgen 0.2.92\n"),
+                Some("0.2.92")
+            );
+            assert_eq!(parse_wasm_bindgen_version("cargo 1.80.0"), None);
+            Ok(())
+        }
+
+        #[test]
+        fn test_task_steps() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop.tasks]
+                lint = { run = [["fmt"], ["clippy", "--all-targets"]] }
+                test = { run = [["test"]], depends = ["lint"] }
+                precommit = { run = [], depends = ["lint", "test"] }
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+
+            let steps = find_task_steps(Path::new("foo.rs"), "precommit", &options)?
+                .iter()
+                .map(|step| format_default_args(step))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                steps,
+                vec![
+                    "wop fmt foo.rs",
+                    "wop clippy foo.rs --all-targets",
+                    "wop test foo.rs"
+                ]
+            );
+            assert!(find_task_steps(Path::new("foo.rs"), "unknown", &options).is_err());
+
+            let cyclic: Value = toml::from_str(
+                r#"
+                [cargo-wop.tasks]
+                a = { run = [["fmt"]], depends = ["b"] }
+                b = { run = [["fmt"]], depends = ["a"] }
+                "#,
+            )?;
+            let options = parse_custom_section(&cyclic)?;
+            assert!(find_task_steps(Path::new("foo.rs"), "a", &options).is_err());
+
+            let nested: Value = toml::from_str(
+                r#"
+                [cargo-wop.tasks]
+                a = { run = [["task", "b"]] }
+                b = { run = [["fmt"], ["task", "c"]] }
+                c = { run = [["task", "a"]] }
+                d = { run = [["task", "d"]] }
+                e = { run = [["task", "b"], ["fmt"]] }
+                "#,
+            )?;
+            let options = parse_custom_section(&nested)?;
+            assert!(find_task_steps(Path::new("foo.rs"), "a", &options).is_err());
+            assert!(find_task_steps(Path::new("foo.rs"), "d", &options).is_err());
+            assert!(find_task_steps(Path::new("foo.rs"), "e", &options).is_err());
+
+            let nested: Value = toml::from_str(
+                r#"
+                [cargo-wop.tasks]
+                lint = { run = [["fmt"]] }
+                ci = { run = [["task", "lint"], ["test"]], depends = ["lint"] }
+                "#,
+            )?;
+            let options = parse_custom_section(&nested)?;
+            let steps = find_task_steps(Path::new("foo.rs"), "ci", &options)?
+                .iter()
+                .map(|step| format_default_args(step))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                steps,
+                vec!["wop fmt foo.rs", "wop task foo.rs lint", "wop test foo.rs"]
+            );
+
+            let unknown_dependency: Value =
+                toml::from_str("[cargo-wop.tasks]\na = { run = [], depends = [\"b\"] }")?;
+            assert!(parse_custom_section(&unknown_dependency).is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_post_build_hooks() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                post-build = [
+                    ["strip", "{artifact}"],
+                    { command = ["wasm-opt", "{artifact}"], artifacts = "*.wasm" },
+                    ["echo", "{out_dir}"],
+                    { command = ["touch", "done"], artifacts = "*.wasm" },
+                ]
+                run-in = "script-dir"
+                env = { DATA = "{script_dir}/data" }
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+            let hooks = &options.post_build;
+
+            assert_eq!(hooks.len(), 4);
+            assert!(hooks[0].is_per_artifact());
+            assert!(hooks[0].matches(Path::new("out/example.so")));
+            assert!(!hooks[1].matches(Path::new("out/example.so")));
+            assert!(hooks[1].matches(Path::new("out/example.wasm")));
+            assert!(!hooks[2].is_per_artifact());
+
+            let command = hooks[2].build_command(None, Path::new("out"), "release")?;
+            assert_eq!(format_command(&command), "echo out");
+
+            let project_info = example_project_info(&manifest)?;
+            let calls = build_post_build_calls(
+                &project_info,
+                &[PathBuf::from("out/example.so")],
+                Path::new("out"),
+                "release",
+            )?;
+            let calls = calls
+                .iter()
+                .map(|(_, command)| format_command(command))
+                .collect::<Vec<_>>();
+            assert_eq!(calls, vec!["strip out/example.so", "echo out"]);
+
+            let calls = build_post_build_calls(
+                &project_info,
+                &[PathBuf::from("out/example.wasm")],
+                Path::new("out"),
+                "release",
+            )?;
+            assert_eq!(calls.len(), 4);
+            for (_, command) in &calls {
+                assert_eq!(command.get_current_dir(), Some(Path::new("/scripts")));
+                let envs = command.get_envs().collect::<Vec<_>>();
+                assert!(envs.contains(&(OsStr::new("DATA"), Some(OsStr::new("/scripts/data")))));
+                assert!(envs.contains(&(
+                    OsStr::new("CARGO_WOP_SCRIPT_PATH"),
+                    Some(OsStr::new("/scripts/example.rs"))
+                )));
+            }
+
+            for invalid in &[
+                "[cargo-wop]\npost-build = [[]]",
+                "[cargo-wop]\npost-build = [\"strip\"]",
+                "[cargo-wop]\npost-build = [{ command = [\"strip\"], unknown = 1 }]",
+            ] {
+                assert!(parse_custom_section(&toml::from_str(invalid)?).is_err());
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn test_apply_filter() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop.filter]
+                "*.pdb" = ""
+                "lib{name}.{ext}" = "{name}.{ext}"
+                "libexample.so" = "example.pyd"
+                "#,
+            )?;
+            let options = parse_custom_section(&manifest)?;
+            let apply = |name: &str| -> Result<(OsString, Option<usize>)> {
+                apply_filter(OsStr::new(name), &options)
+            };
+
+            assert_eq!(apply("example.pdb")?, (OsString::from(""), Some(0)));
+            assert_eq!(
+                apply("libexample.so")?,
+                (OsString::from("example.pyd"), Some(2))
+            );
+            assert_eq!(apply("libother.so")?, (OsString::from("other.so"), Some(1)));
+            assert_eq!(apply("example.exe")?, (OsString::from("example.exe"), None));
+
+            let used = [0, 1].iter().copied().collect::<HashSet<_>>();
+            let usage = FilterUsage {
+                filter: options.filter.clone(),
+                target_filter: 0,
+                used,
+            };
+            assert!(check_strict_filter(&[usage]).is_err());
+
+            let entry = |src: &str| (String::from(src), String::new());
+            let usage =
+                |filter: Vec<(String, String)>, target_filter, used: &[usize]| FilterUsage {
+                    filter,
+                    target_filter,
+                    used: used.iter().copied().collect(),
+                };
+            let wasm = vec![entry("*.wasm"), entry("*.d")];
+            let native = vec![entry("*.d")];
+            assert!(check_strict_filter(&[
+                usage(wasm.clone(), 1, &[0]),
+                usage(native.clone(), 0, &[0])
+            ])
+            .is_ok());
+            assert!(
+                check_strict_filter(&[usage(wasm.clone(), 1, &[0]), usage(native, 0, &[])])
+                    .is_err()
+            );
+            assert!(check_strict_filter(&[usage(wasm, 1, &[1])]).is_err());
+            Ok(())
+        }
+
+        #[test]
+        fn test_artifact_kind_file_name() {
+            use ArtifactKind::*;
+
+            let python = |name: &str| artifact_kind_file_name(PythonExtension, name, None);
+            assert_eq!(python("libexample.so").as_deref(), Some("example.so"));
+            assert_eq!(python("libexample.dylib").as_deref(), Some("example.so"));
+            assert_eq!(python("example.dll").as_deref(), Some("example.pyd"));
+            assert_eq!(python("example.pdb"), None);
+            assert_eq!(python("example.so"), None);
+
+            assert_eq!(
+                artifact_kind_file_name(
+                    PythonExtension,
+                    "libexample.so",
+                    Some(".cpython-311-x86_64-linux-gnu.so")
+                )
+                .as_deref(),
+                Some("example.cpython-311-x86_64-linux-gnu.so")
+            );
+            assert_eq!(
+                artifact_kind_file_name(NodeAddon, "example.dll", None).as_deref(),
+                Some("example.node")
+            );
+            assert_eq!(
+                artifact_kind_file_name(Default, "libexample.so", None),
+                None
+            );
+        }
+
+        #[test]
+        fn test_remove_arg() {
+            let args = [
+                "--message-format",
+                "short",
+                "-v",
+                "--message-format=json",
+                "--",
+                "--message-format",
+            ]
+            .iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+            let expected = ["-v", "--", "--message-format"]
+                .iter()
+                .map(OsString::from)
+                .collect::<Vec<_>>();
+            assert_eq!(remove_arg(&args, "--message-format"), expected);
+        }
+
+        #[test]
+        fn test_resolve_profile() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [cargo-wop]
+                profile = "profiling"
+
+                [profile.profiling]
+                inherits = "release"
+                debug = true
+                "#,
+            )?;
+            let mut project_info = example_project_info(&manifest)?;
+            project_info.manifest = manifest;
+
+            let resolve = |call: CargoCall| -> Result<Vec<OsString>> {
+                let spec = find_command_spec(&call.command).unwrap_or_default();
+                Ok(resolve_profile(call.normalize(&spec)?, &project_info)?.args)
+            };
+            let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+
+            assert_eq!(
+                resolve(CargoCall::new("build", "example.rs"))?,
+                args(&["--profile", "profiling"])
+            );
+            assert_eq!(
+                resolve(CargoCall::new("run", "example.rs").with_args(["-v", "--", "arg"]))?,
+                args(&["-v", "--profile", "profiling", "--", "arg"])
+            );
+            assert_eq!(
+                resolve(CargoCall::new("build", "example.rs").with_profile("release"))?,
+                args(&["--release"])
+            );
+            assert_eq!(
+                resolve(CargoCall::new("build", "example.rs").with_args(["--release"]))?,
+                args(&["--release"])
+            );
+            assert_eq!(resolve(CargoCall::new("check", "example.rs"))?, args(&[]));
+            assert!(
+                resolve(CargoCall::new("build", "example.rs").with_profile("unknown")).is_err()
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn test_resolve_profile_command() -> Result<()> {
+            let manifest: Value = toml::from_str(
+                r#"
+                [profile.profiling]
+                inherits = "release"
+
+                [profile.custom]
+                inherits = "dev"
+
+                [cargo-wop.commands]
+                run-custom = {}
+                "#,
+            )?;
+            let mut project_info = example_project_info(&manifest)?;
+            project_info.manifest = manifest;
+
+            let call = CargoCall::new("run-profiling", "example.rs").with_args(["-v", "arg"]);
+            let mut toolchain_call = call.clone();
+            toolchain_call.options.toolchain = Some(String::from("nightly"));
+            assert_eq!(
+                resolve_profile_command(&toolchain_call, &project_info)?,
+                Some({
+                    let mut expected = CargoCall::new("run", "example.rs")
+                        .with_profile("profiling")
+                        .with_args(["-v", "arg"]);
+                    expected.options.toolchain = Some(String::from("nightly"));
+                    expected.into_args()
+                }),
+            );
+
+            // configured commands are forwarded
+            let call = CargoCall::new("run-custom", "example.rs");
+            assert_eq!(resolve_profile_command(&call, &project_info)?, None);
+
+            // undeclared profiles and typos are rejected instead of forwarded
+            for command in ["run-wasm", "build-profilng", "build-script"] {
+                let call = CargoCall::new(command, "example.rs");
+                let err = resolve_profile_command(&call, &project_info).unwrap_err();
+                assert!(err.to_string().starts_with("Unknown profile"));
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn test_find_build_profile() -> Result<()> {
+            let project_info = example_project_info(&Value::Table(Default::default()))?;
+            let spec = find_command_spec("build").unwrap_or_default();
+            let call = CargoCall::new("build", "example.rs").normalize(&spec)?;
+            let call = resolve_profile(call, &project_info)?;
+            assert_eq!(find_build_profile(&call)?, "release");
+
+            let call = CargoCall::new("build", "example.rs").with_args(["--profile", "dev"]);
+            assert_eq!(find_build_profile(&call)?, "debug");
+
+            let call = CargoCall::new("build", "example.rs").with_args(["--profile", "bench"]);
+            assert_eq!(find_build_profile(&call)?, "release");
+
+            let call = CargoCall::new("build", "example.rs").with_args(["--profile", "test"]);
+            assert_eq!(find_build_profile(&call)?, "debug");
+
+            let call = CargoCall::new("build", "example.rs").with_args(["--profile=profiling"]);
+            assert_eq!(find_build_profile(&call)?, "profiling");
+            Ok(())
+        }
+
+        #[test]
+        #[allow(clippy::needless_borrows_for_generic_args)]
+        fn test_merge_default_args() {
+            assert_eq!(
+                merge_default_args(DefaultAction::new("foo.rs"), None),
+                to_strings(&["wop", "run", "foo.rs"]),
+            );
+
+            assert_eq!(
+                merge_default_args(
+                    DefaultAction::new("foo.rs").with_args(&["--", "hello", "world"]),
+                    None
+                ),
+                to_strings(&["wop", "run", "foo.rs", "--", "hello", "world"]),
+            );
+
+            assert_eq!(
+                merge_default_args(
+                    DefaultAction::new("foo.rs").with_args(&["test", "--", "hello", "world"]),
+                    Some(&[])
+                ),
+                to_strings(&["wop", "test", "foo.rs", "--", "hello", "world"]),
+            );
+
+            assert_eq!(
+                merge_default_args(
+                    DefaultAction::new("foo.rs"),
+                    Some(&["build", "--target", "wasm32-unknown-unknown"])
+                ),
+                to_strings(&[
+                    "wop",
+                    "build",
+                    "foo.rs",
+                    "--target",
+                    "wasm32-unknown-unknown"
+                ]),
+            );
+        }
+    }
+}
+
+mod install_records {
+    use std::{
+        fmt::Write,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{anyhow, Context, Result};
+    use serde_json::{json, Value};
+    use sha1::Sha1;
+
+    const RECORDS_FILE: &str = "installed.json";
+
+    /// A script installed via `cargo wop install`
+    ///
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct InstallRecord {
+        pub script: PathBuf,
+        pub package: String,
+        pub binaries: Vec<String>,
+        /// The hash of the script at installation time
+        pub hash: String,
+    }
+
+    impl InstallRecord {
+        pub fn new(script: &Path, package: &str, binaries: Vec<String>) -> Result<Self> {
+            Ok(Self {
+                script: script.to_owned(),
+                package: package.to_owned(),
+                binaries,
+                hash: hash_file(script)?,
+            })
+        }
+
+        /// Whether the script changed since installation
+        ///
+        /// Deleted scripts are not considered stale, as they cannot be
+        /// reinstalled.
+        ///
+        pub fn is_stale(&self) -> bool {
+            match hash_file(&self.script) {
+                Ok(hash) => hash != self.hash,
+                Err(_) => false,
+            }
+        }
+
+        fn status(&self) -> &'static str {
+            if !self.script.exists() {
+                "deleted"
+            } else if self.is_stale() {
+                "changed"
+            } else {
+                "up to date"
+            }
+        }
+
+        fn to_json(&self) -> Value {
+            json!({
+                "script": self.script.to_string_lossy(),
+                "package": self.package,
+                "binaries": self.binaries,
+                "hash": self.hash,
+            })
+        }
+
+        fn from_json(value: &Value) -> Result<Self> {
+            let get_str = |key: &str| -> Result<String> {
+                value
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("Invalid install record: {} is not a string", key))
+            };
+
+            let binaries = value
+                .get("binaries")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("Invalid install record: binaries is not an array"))?
+                .iter()
+                .map(|b| {
+                    b.as_str()
+                        .map(str::to_owned)
+                        .ok_or_else(|| anyhow!("Invalid install record: binary is not a string"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            Ok(Self {
+                script: PathBuf::from(get_str("script")?),
+                package: get_str("package")?,
+                binaries,
+                hash: get_str("hash")?,
+            })
+        }
+    }
+
+    /// The scripts installed via `cargo wop install`, stored in the cache dir
+    ///
+    #[derive(Debug, Default)]
+    pub struct InstallRecords {
+        records: Vec<InstallRecord>,
+    }
+
+    impl InstallRecords {
+        pub fn load(cache_dir: &Path) -> Result<Self> {
+            let path = cache_dir.join(RECORDS_FILE);
+            if !path.exists() {
+                return Ok(Self::default());
+            }
+
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Cannot read {}", path.display()))?;
+            let value: Value = serde_json::from_str(&content)
+                .with_context(|| format!("Cannot parse {}", path.display()))?;
+
+            let records = value
+                .as_array()
+                .ok_or_else(|| anyhow!("Invalid install records: not an array"))?
+                .iter()
+                .map(InstallRecord::from_json)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Self { records })
+        }
+
+        pub fn store(&self, cache_dir: &Path) -> Result<()> {
+            let value = Value::Array(self.records.iter().map(InstallRecord::to_json).collect());
+            fs::create_dir_all(cache_dir)?;
+            fs::write(
+                cache_dir.join(RECORDS_FILE),
+                serde_json::to_string_pretty(&value)?,
+            )?;
+            Ok(())
+        }
+
+        /// Insert a record, replacing any previous record of the same script
+        pub fn insert(&mut self, record: InstallRecord) {
+            self.remove(&record.script);
+            self.records.push(record);
+        }
+
+        pub fn remove(&mut self, script: &Path) {
+            self.records.retain(|record| record.script != script);
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &InstallRecord> {
+            self.records.iter()
+        }
+
+        /// Format the records as shown by `cargo wop installed`
+        pub fn format(&self) -> String {
+            let mut res = String::new();
+            if self.records.is_empty() {
+                writeln!(res, "No scripts installed").unwrap();
+                return res;
+            }
+
+            for record in &self.records {
+                writeln!(
+                    res,
+                    "{} ({}): {}",
+                    record.script.display(),
+                    record.status(),
+                    record.binaries.join(", ")
+                )
+                .unwrap();
+            }
+            res
+        }
+    }
+
+    fn hash_file(path: &Path) -> Result<String> {
+        let content = fs::read(path).with_context(|| format!("Cannot read {}", path.display()))?;
+        let mut hash = Sha1::new();
+        hash.update(&content);
+        Ok(hash.digest().to_string())
+    }
+}
+
+mod execution_env {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{bail, Context, Result};
+
+    /// The environment the command is executed in
+    ///
+    /// It's defined as a trait to mock it out in tests.
+    ///
+    pub trait ExecutionEnv: Clone {
+        fn get_cargo_home_dir(&self) -> PathBuf;
+        fn get_working_directory(&self) -> PathBuf;
+        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf>;
+    }
+
+    #[derive(Clone)]
+    pub struct StdExecutionEnv {
+        working_directory: PathBuf,
+        cargo_directory: PathBuf,
+    }
+
+    impl StdExecutionEnv {
+        pub fn new() -> Result<Self> {
+            let this = Self {
+                working_directory: std::env::current_dir()?,
+                cargo_directory: find_cargo_home_dir()?,
+            };
+            Ok(this)
+        }
+    }
+
+    impl ExecutionEnv for StdExecutionEnv {
+        fn get_cargo_home_dir(&self) -> PathBuf {
+            self.cargo_directory.clone()
+        }
+
+        fn get_working_directory(&self) -> PathBuf {
+            self.working_directory.clone()
+        }
+
+        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+            let p = self.working_directory.join(path);
+            let p = p
+                .canonicalize()
+                .with_context(|| format!("Cannot canonicalize {}", p.display()))?;
+            Ok(p)
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct LocalEnv {
+        working_directory: PathBuf,
+        cargo_directory: PathBuf,
+    }
+
+    impl LocalEnv {
+        pub fn from_env(env: &impl ExecutionEnv) -> Self {
+            Self {
+                working_directory: env.get_working_directory(),
+                cargo_directory: env.get_cargo_home_dir(),
+            }
+        }
+    }
+
+    impl ExecutionEnv for LocalEnv {
+        fn get_cargo_home_dir(&self) -> PathBuf {
+            self.cargo_directory.clone()
+        }
+
+        fn get_working_directory(&self) -> PathBuf {
+            self.working_directory.clone()
+        }
+
+        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+            Ok(path.as_ref().into())
+        }
+    }
+
+    /// Find the cargo home
+    ///
+    /// Follow the documentation found
+    /// [here](https://doc.rust-lang.org/cargo/reference/environment-variables.html).
+    ///
+    fn find_cargo_home_dir() -> Result<PathBuf> {
+        if let Some(cargo_home) = std::env::var_os("CARGO_HOME") {
+            let cargo_home = PathBuf::from(cargo_home);
+            return Ok(cargo_home);
+        }
+
+        let env_var = if std::env::consts::OS != "windows" {
+            "HOME"
+        } else {
+            "USERPROFILE"
+        };
+
+        // TODO: handle windows
+        if let Some(user_home) = std::env::var_os(env_var) {
+            let mut user_home = PathBuf::from(user_home);
+            user_home.push(".cargo");
+            return Ok(user_home);
+        }
+
+        bail!("Could not determine cargo home directory");
+    }
+}
+
+mod manifest_normalization {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{anyhow, bail, ensure, Context, Result};
+    use toml::{value::Table, Value};
+
+    use super::{execution_env::ExecutionEnv, util::to_utf8_string};
+
+    /// The edition used if the embedded manifest does not specify one
+    pub const DEFAULT_EDITION: &str = "2018";
+
+    /// Normalize the embedded manifest that it can be used to build the target
+    ///
+    /// This function inserts a package section with name, version, and edition. It
+    /// makes sure at least a single target exists (a binary as a default). For each
+    /// target it sets the correct path to the source file.
+    ///
+    pub fn normalize_manifest(
+        mut manifest: Value,
+        target_path: impl AsRef<Path>,
+        env: &impl ExecutionEnv,
+    ) -> Result<Value> {
+        let target_path = target_path.as_ref();
+        let target_directory = target_path
+            .parent()
+            .ok_or_else(|| anyhow!("Cannot get parent of target path"))?
+            .to_owned();
+
+        let target_name = to_utf8_string(
+            target_path
+                .file_stem()
+                .ok_or_else(|| anyhow!("Cannot build manifest for non file target"))?,
+        )?;
+
+        let root = manifest
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Can only handle manifests that are tables"))?;
+
+        strip_custom_section(root);
+        ensure_valid_package(root, &target_name).context("Error while modifying package")?;
+        ensure_at_least_a_single_target(root, target_path)
+            .context("Error while ensuring a valid target")?;
+
+        patch_all_targets(root, target_path, &target_name, env)
+            .context("Error while patching the targets")?;
+        normalize_paths(root, &target_directory, env)
+            .context("Error while normalizing the file paths")?;
+
+        Ok(manifest)
+    }
+
+    /// Strip the custom section used to configure cargo-wop
+    ///
+    fn strip_custom_section(root: &mut toml::map::Map<String, Value>) {
+        root.remove("cargo-wop");
+    }
+
+    /// Helper for normalize_manifest: Ensure the package table is correctly filled
+    ///
+    fn ensure_valid_package(root: &mut toml::map::Map<String, Value>, name: &str) -> Result<()> {
+        if !root.contains_key("package") {
+            root.insert(String::from("package"), Value::Table(Default::default()));
+        }
+        let package = root
+            .get_mut("package")
+            .unwrap()
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Invalid manifest: package is not a table"))?;
+
+        if !package.contains_key("name") {
+            package.insert(String::from("name"), Value::from(name));
+        }
+        if !package.contains_key("version") {
+            package.insert(String::from("version"), Value::from("0.1.0"));
+        }
+
+        if !package.contains_key("edition") {
+            package.insert(String::from("edition"), Value::from(DEFAULT_EDITION));
+        }
+
+        Ok(())
+    }
+
+    /// Helper for normalize manifest: Ensure at least a single target is available
+    ///
+    fn ensure_at_least_a_single_target(
+        root: &mut toml::map::Map<String, Value>,
+        target_path: &Path,
+    ) -> Result<()> {
+        let has_single_bin = root.contains_key("bin")
+            && root
+                .get("bin")
+                .and_then(|b| b.as_array())
+                .map(|b| !b.is_empty())
+                .ok_or_else(|| anyhow!("Invalid manifest"))?;
+
+        // NOTE: cargo accepts manifests that only define test or bench targets,
+        // but the script is only built as such if all of them point to it
+        let script_dir = target_path.parent().unwrap_or_else(|| Path::new(""));
+        let test_targets = ["test", "bench"]
+            .iter()
+            .filter_map(|key| root.get(*key).and_then(|t| t.as_array()))
+            .flatten()
+            .collect::<Vec<_>>();
+        let has_only_tests = !test_targets.is_empty()
+            && test_targets.iter().all(|target| {
+                match target.get("path").and_then(|p| p.as_str()) {
+                    Some(path) => script_dir.join(path) == target_path,
+                    None => true,
+                }
+            });
+        let has_definition = root.contains_key("lib") || has_single_bin || has_only_tests;
+
+        if has_definition {
+            return Ok(());
+        }
+
+        root.insert(String::from("bin"), Value::Array(Default::default()));
+
+        let bins = root
+            .get_mut("bin")
+            .unwrap()
+            .as_array_mut()
+            .ok_or_else(|| anyhow!("Invalid manifest: bin is not an array"))?;
+        if bins.is_empty() {
+            bins.push(Value::Table(Default::default()));
+        }
+
+        Ok(())
+    }
+
+    /// Patch all available target definition
+    fn patch_all_targets(
+        root: &mut toml::map::Map<String, Value>,
+        path: &Path,
+        name: &str,
+        env: &impl ExecutionEnv,
+    ) -> Result<()> {
+        if let Some(lib) = root.get_mut("lib") {
+            patch_target(lib, path, name, env)?;
+        }
+
+        if let Some(bins) = root.get_mut("bin") {
+            let bins = bins
+                .as_array_mut()
+                .ok_or_else(|| anyhow!("Invalid manifest: bin not an array"))?;
+
+            for bin in bins {
+                patch_target(bin, path, name, env)?;
+            }
+        }
+
+        // test and bench targets may point to other files, only patch them if
+        // no path is given
+        for key in &["test", "bench"] {
+            let targets = match root.get_mut(*key) {
+                Some(targets) => targets,
+                None => continue,
+            };
+            let targets = targets
+                .as_array_mut()
+                .ok_or_else(|| anyhow!("Invalid manifest: {} not an array", key))?;
+
+            for target in targets {
+                let has_path = target
+                    .as_table()
+                    .ok_or_else(|| anyhow!("Cannot patch non table target"))?
+                    .contains_key("path");
+                if !has_path {
+                    patch_target(target, path, name, env)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a library target to run the doctests of the script
+    ///
+    /// cargo only runs the doctests of library targets. The added library is
+    /// named after the package, as the examples would refer to a library of
+    /// the script, and is excluded from unit tests, benchmarks and the
+    /// documentation to not duplicate the tests of the binary. In the library
+    /// `fn main` and its helpers are unused, therefore the `dead_code` lint is
+    /// allowed for the package. An existing library target is kept, but it is
+    /// also compiled as an rlib, as doctests require it.
+    ///
+    /// The library has to use the script itself as its root: wrapping it as a
+    /// module of another file would change what `crate::` refers to. Therefore
+    /// cargo warns that the file is present in multiple build targets.
+    ///
+    pub fn patch_doctest_target(manifest: &mut Value, path: &Path) -> Result<()> {
+        let root = manifest
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Can only handle manifests that are tables"))?;
+
+        if let Some(lib) = root.get_mut("lib") {
+            let lib = lib
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("Invalid manifest: lib is not a table"))?;
+            if let Some(crate_types) = lib.get_mut("crate-type") {
+                let crate_types = crate_types
+                    .as_array_mut()
+                    .ok_or_else(|| anyhow!("Invalid manifest: crate-type is not an array"))?;
+                let has_rlib = crate_types
+                    .iter()
+                    .any(|crate_type| matches!(crate_type.as_str(), Some("lib" | "rlib")));
+                if !has_rlib {
+                    crate_types.push(Value::from("rlib"));
+                }
+            }
+            return Ok(());
+        }
+
+        let package_name = root
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Invalid manifest: package name is not a string"))?;
+        let name = package_name.replace('-', "_");
+
+        let mut lib = Table::new();
+        lib.insert(String::from("name"), Value::from(name));
+        lib.insert(
+            String::from("path"),
+            Value::from(to_utf8_string(path.as_os_str())?),
+        );
+        lib.insert(String::from("crate-type"), Value::from(vec!["rlib"]));
+        lib.insert(String::from("test"), Value::from(false));
+        lib.insert(String::from("bench"), Value::from(false));
+        lib.insert(String::from("doc"), Value::from(false));
+        lib.insert(String::from("doctest"), Value::from(true));
+        root.insert(String::from("lib"), Value::Table(lib));
+
+        let lints = root
+            .entry("lints")
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Invalid manifest: lints is not a table"))?;
+        lints
+            .entry("rust")
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Invalid manifest: lints.rust is not a table"))?
+            .entry("dead_code")
+            .or_insert_with(|| Value::from("allow"));
+
+        Ok(())
+    }
+
+    /// Helper for normalize manifest: patch the target definition to use the correct file path
+    fn patch_target(
+        target: &mut Value,
+        path: &Path,
+        name: &str,
+        env: &impl ExecutionEnv,
+    ) -> Result<()> {
+        let path = env.normalize(path)?;
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Cannot interpret path as UTF-8 string"))?
+            .to_owned();
+
+        let bin = target
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Cannot patch non table target"))?;
+        bin.insert(String::from("path"), Value::String(path));
+
+        if !bin.contains_key("name") {
+            bin.insert(String::from("name"), Value::String(name.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// A publishable package layout derived from a normalized manifest
+    ///
+    #[derive(Debug)]
+    pub struct PackageLayout {
+        pub manifest: Value,
+        /// The files to copy into the package as (source, relative destination)
+        pub files: Vec<(PathBuf, PathBuf)>,
+    }
+
+    /// Build a package layout with relative paths from a normalized manifest
+    ///
+    /// The script is placed at `src/lib.rs` (or `src/main.rs` for executables)
+    /// and the build script at `build.rs`. Path dependencies are only
+    /// supported if they also specify a version, as the absolute paths
+    /// inserted during normalization are not usable for other users.
+    ///
+    pub fn package_manifest(mut manifest: Value, script_path: &Path) -> Result<PackageLayout> {
+        let root = manifest
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Can only handle manifests that are tables"))?;
+        let script_dir = script_path
+            .parent()
+            .ok_or_else(|| anyhow!("Cannot get parent of script path"))?;
+
+        let source = if root.contains_key("lib") {
+            "src/lib.rs"
+        } else {
+            "src/main.rs"
+        };
+        let mut files = vec![(script_path.to_owned(), PathBuf::from(source))];
+
+        package_targets(root, script_path, source).context("Error while packaging the targets")?;
+        package_dependencies(root).context("Error while packaging the dependencies")?;
+
+        let package = root
+            .get_mut("package")
+            .and_then(Value::as_table_mut)
+            .ok_or_else(|| anyhow!("Invalid manifest: package is not a table"))?;
+
+        if let Some(build) = package.get("build").and_then(Value::as_str) {
+            files.push((PathBuf::from(build), PathBuf::from("build.rs")));
+            package.insert(String::from("build"), Value::from("build.rs"));
+        }
+
+        for key in &["readme", "license-file"] {
+            let path = match package.get(*key).and_then(Value::as_str) {
+                Some(path) => script_dir.join(path),
+                None => continue,
+            };
+            let file_name = path
+                .file_name()
+                .and_then(|p| p.to_str())
+                .ok_or_else(|| anyhow!("Invalid {}", key))?
+                .to_owned();
+
+            files.push((path, PathBuf::from(&file_name)));
+            package.insert(String::from(*key), Value::from(file_name));
+        }
+
+        Ok(PackageLayout { manifest, files })
+    }
+
+    fn package_targets(
+        root: &mut toml::map::Map<String, Value>,
+        script_path: &Path,
+        source: &str,
+    ) -> Result<()> {
+        let script_path = script_path
+            .to_str()
+            .ok_or_else(|| anyhow!("Cannot interpret path as UTF-8 string"))?;
+
+        let mut targets = Vec::new();
+        for (key, value) in root.iter_mut() {
+            match key.as_str() {
+                "lib" => targets.push(value),
+                "bin" | "test" | "bench" | "example" => {
+                    let value = value
+                        .as_array_mut()
+                        .ok_or_else(|| anyhow!("Invalid manifest: {} not an array", key))?;
+                    targets.extend(value.iter_mut());
+                }
+                _ => {}
+            }
+        }
+
+        for target in targets {
+            let target = target
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("Cannot package non table target"))?;
+            let path = target.get("path").and_then(Value::as_str);
+            ensure!(
+                path == Some(script_path),
+                "Cannot package targets that refer to other files than the script: {}",
+                path.unwrap_or_default(),
+            );
+            target.insert(String::from("path"), Value::from(source));
+        }
+
+        Ok(())
+    }
+
+    fn package_dependencies(root: &mut toml::map::Map<String, Value>) -> Result<()> {
+        const DEPENDENCY_KEYS: &[&str] =
+            &["dependencies", "dev-dependencies", "build-dependencies"];
+
+        let mut tables = Vec::new();
+        for (key, value) in root.iter_mut() {
+            if DEPENDENCY_KEYS.contains(&key.as_str()) {
+                tables.push(value);
+            } else if key == "target" {
+                let platforms = value
+                    .as_table_mut()
+                    .ok_or_else(|| anyhow!("Invalid manifest: target is not a table"))?;
+                for (_, platform) in platforms.iter_mut() {
+                    let platform = platform
+                        .as_table_mut()
+                        .ok_or_else(|| anyhow!("Invalid manifest: target entry is not a table"))?;
+                    for (key, value) in platform.iter_mut() {
+                        if DEPENDENCY_KEYS.contains(&key.as_str()) {
+                            tables.push(value);
+                        }
+                    }
+                }
+            }
+        }
+
+        for table in tables {
+            let table = table
+                .as_table_mut()
+                .ok_or_else(|| anyhow!("Invalid manifest: dependencies are not a table"))?;
+
+            for (name, dependency) in table.iter_mut() {
+                let dependency = match dependency.as_table_mut() {
+                    Some(dependency) => dependency,
+                    None => continue,
+                };
+                if !dependency.contains_key("path") {
+                    continue;
+                }
+                ensure!(
+                    dependency.contains_key("version"),
+                    "Cannot package the path dependency {}, it requires a version",
+                    name
+                );
+                dependency.remove("path");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The key path & mode for normalization
+    ///
+    /// Use an empty path to denote arrays or arbitrary children. All paths are
+    /// interpreted as relative to the project source path.
+    ///
+    const PATH_NORMALIZATION: &[&[&str]] = &[
+        &["lib", "path"],
+        &["bin", "", "path"],
+        &["example", "", "path"],
+        &["test", "", "path"],
+        &["bench", "", "path"],
+        &["package", "build"],
+        &["dependencies", "", "path"],
+        &["dev-dependencies", "", "path"],
+        &["build-dependencies", "", "path"],
+        &["patch", "", "", "path"],
+        &["target", "", "dependencies", "", "path"],
+    ];
+
+    fn normalize_paths(
+        root: &mut toml::map::Map<String, Value>,
+        project_source_path: impl AsRef<Path>,
+        env: &impl ExecutionEnv,
+    ) -> Result<()> {
+        let project_source_path = project_source_path.as_ref();
+        for path in PATH_NORMALIZATION.iter().copied() {
+            let child = match root.get_mut(path[0]) {
+                Some(child) => child,
+                None => continue,
+            };
+            _normalize_paths(child, project_source_path, env, path, 1)?;
+        }
+        Ok(())
+    }
+
+    fn _normalize_paths(
+        current: &mut Value,
+        project_source_path: &Path,
+        env: &impl ExecutionEnv,
+        path: &[&str],
+        depth: usize,
+    ) -> Result<()> {
+        if depth + 1 == path.len() {
+            let current = match current.as_table_mut() {
+                Some(current) => current,
+                // NOTE: the containing item does not need to be table, e.g.,
+                // when the dependency is directly assigned to a version
+                None => return Ok(()),
+            };
+
+            _normalize_table_item(current, project_source_path, env, path[depth])?;
+            return Ok(());
+        }
+        match current {
+            Value::Array(current) => {
+                // TODO: improve error message
+                ensure!(
+                    path[depth].is_empty(),
+                    "Unexpected array in path {:?}",
+                    &path[..depth + 1]
+                );
+                for item in current {
+                    _normalize_paths(item, project_source_path, env, path, depth + 1)?;
+                }
+            }
+            Value::Table(current) => {
+                if path[depth].is_empty() {
+                    for (_, item) in current {
+                        _normalize_paths(item, project_source_path, env, path, depth + 1)?;
+                    }
+                } else if current.contains_key(path[depth]) {
+                    _normalize_paths(
+                        &mut current[path[depth]],
+                        project_source_path,
+                        env,
+                        path,
+                        depth + 1,
+                    )?;
+                }
+            }
+            // TODO: improve error message
+            _ => bail!("Invalid value type"),
+        }
+
+        Ok(())
+    }
+
+    fn _normalize_table_item(
+        current: &mut Table,
+        project_source_path: &Path,
+        env: &impl ExecutionEnv,
+        key: &str,
+    ) -> Result<()> {
+        if !current.contains_key(key) {
+            return Ok(());
+        }
+
+        let normed_path = current
+            .get(key)
+            .unwrap()
+            .as_str()
+            .ok_or_else(|| anyhow!("Invalid manifest: non string path"))?;
+        let normed_path = env.normalize(project_source_path.join(normed_path))?;
+
+        let normed_path = normed_path
+            .to_str()
+            .ok_or_else(|| anyhow!("Cannot interpret dependency path a string"))?;
+
+        current[key] = normed_path.into();
+
+        Ok(())
+    }
+}
+
+mod manifest_parsing {
+    use std::io::{BufRead, BufReader, Read};
+
+    use anyhow::{bail, Result};
+    use toml::Value;
+
+    /// Parse the manifest from the initial doc comment
+    ///
+    pub fn parse_manifest(reader: impl Read) -> Result<Value> {
+        let reader = BufReader::new(reader);
+
+        let mut state = ParseState::Start;
+        let mut result = String::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let line_start = LineStart::from(line.as_str());
+
+            state = match (state, line_start) {
+                (ParseState::Start, LineStart::Other) => {
+                    return Ok(Value::Table(Default::default()))
+                }
+                (ParseState::DocComment, LineStart::Other) => {
+                    return Ok(Value::Table(Default::default()))
+                }
+                (ParseState::Start, LineStart::DocComment) => ParseState::DocComment,
+                (ParseState::Start, LineStart::ManifestEnd) => state,
+                (ParseState::DocComment, LineStart::DocComment) => state,
+                (ParseState::DocComment, LineStart::ManifestEnd) => state,
+                (ParseState::Start, LineStart::ManifestStart) => ParseState::Manifest,
+                (ParseState::DocComment, LineStart::ManifestStart) => ParseState::Manifest,
+
+                (ParseState::Manifest, LineStart::DocComment) => {
+                    let line = line.strip_prefix("//!").unwrap().trim_start();
+                    result.push_str(line);
+                    result.push('\n');
+                    state
+                }
+                (ParseState::Manifest, LineStart::ManifestEnd) => {
+                    return Ok(toml::from_str(&result)?)
+                }
+                (ParseState::Manifest, LineStart::ManifestStart) => bail!("Invalid manifest"),
+                (ParseState::Manifest, LineStart::Other) => bail!("Invalid manifest"),
+            };
+        }
+
+        if state == ParseState::Manifest {
+            bail!("Incomplete manifest");
+        }
+
+        Ok(Value::Table(Default::default()))
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum ParseState {
+        Start,
+        DocComment,
+        Manifest,
+    }
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum LineStart {
+        DocComment,
+        ManifestStart,
+        ManifestEnd,
+        Other,
+    }
+
+    impl From<&str> for LineStart {
+        fn from(line: &str) -> Self {
+            if line.starts_with("//! ```cargo") {
+                Self::ManifestStart
+            } else if line.starts_with("//! ```") {
+                Self::ManifestEnd
+            } else if line.starts_with("//!") {
+                Self::DocComment
+            } else {
+                Self::Other
+            }
+        }
+    }
+}
+
+mod templates {
+    use std::{
+        fmt::Write,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use anyhow::{anyhow, bail, Context, Result};
+
+    use super::{
+        execution_env::ExecutionEnv, manifest_normalization::DEFAULT_EDITION, text,
+        util::format_dynamic,
+    };
+
+    /// The templates that ship with cargo-wop as (name, description, source)
+    ///
+    const BUILTIN_TEMPLATES: &[(&str, &str, &str)] = &[
+        ("--bin", "an executable", text::TEMPLATE_BIN),
+        (
+            "--cli",
+            "a command line tool using clap",
+            text::TEMPLATE_CLI,
+        ),
+        (
+            "--tokio",
+            "an executable with an async main using tokio",
+            text::TEMPLATE_TOKIO,
+        ),
+        ("--lib", "a library", text::TEMPLATE_LIB),
+        (
+            "--staticlib",
+            "a static library with a C interface",
+            text::TEMPLATE_STATICLIB,
+        ),
+        (
+            "--proc-macro",
+            "a procedural macro crate",
+            text::TEMPLATE_PROC_MACRO,
+        ),
+        (
+            "--pymodule",
+            "a library using PyO3 that compiles to a Python extension module",
+            text::TEMPLATE_PYMODULE,
+        ),
+        ("--wasm", "a standalone wasm32 module", text::TEMPLATE_WASM),
+        (
+            "--wasm-bindgen",
+            "a wasm32 module with JS bindings generated by wasm-bindgen",
+            text::TEMPLATE_WASM_BINDGEN,
+        ),
+        (
+            "--bench",
+            "benchmarks using criterion",
+            text::TEMPLATE_BENCH,
+        ),
+        (
+            "--test",
+            "integration tests without any other targets",
+            text::TEMPLATE_TEST,
+        ),
+    ];
+
+    /// A template that can be used to create new source files
+    ///
+    #[derive(Debug)]
+    pub struct Template {
+        pub name: String,
+        pub description: String,
+        pub source: TemplateSource,
+    }
+
+    #[derive(Debug)]
+    pub enum TemplateSource {
+        Builtin(&'static str),
+        File(PathBuf),
+    }
+
+    impl Template {
+        /// Load the template text
+        pub fn load(&self) -> Result<String> {
+            match &self.source {
+                TemplateSource::Builtin(source) => Ok((*source).to_owned()),
+                TemplateSource::File(path) => fs::read_to_string(path)
+                    .with_context(|| format!("Cannot read template {}", path.display())),
+            }
+        }
+    }
+
+    /// The directories searched for user templates, in increasing priority
+    ///
+    /// User templates are read from `$CARGO_HOME/wop-templates`, project
+    /// templates from `.wop-templates` in the current working directory.
+    ///
+    pub fn template_dirs(env: &impl ExecutionEnv) -> Vec<PathBuf> {
+        vec![
+            env.get_cargo_home_dir().join("wop-templates"),
+            env.get_working_directory().join(".wop-templates"),
+        ]
+    }
+
+    /// Find all available templates
+    ///
+    /// Templates found in later directories overwrite templates of the same
+    /// name in earlier ones, including the builtin templates.
+    ///
+    pub fn find_templates(env: &impl ExecutionEnv) -> Result<Vec<Template>> {
+        let mut result = Vec::new();
+        for (name, description, source) in BUILTIN_TEMPLATES.iter().copied() {
+            result.push(Template {
+                name: name.to_owned(),
+                description: description.to_owned(),
+                source: TemplateSource::Builtin(source),
+            });
+        }
+
+        for dir in template_dirs(env) {
+            if !dir.is_dir() {
+                continue;
+            }
+
+            let mut paths = Vec::new();
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+
+            for path in paths {
+                let template = load_template_file(path)?;
+                result.retain(|t| t.name != template.name);
+                result.push(template);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Find the template with the given name, e.g., `--bin`
+    ///
+    pub fn find_template(name: &str, env: &impl ExecutionEnv) -> Result<Template> {
+        find_templates(env)?
+            .into_iter()
+            .find(|t| t.name == name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown template '{}'. Use 'cargo wop new' to list available templates",
+                    name
+                )
+            })
+    }
+
+    fn load_template_file(path: PathBuf) -> Result<Template> {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow!("Invalid template file name {}", path.display()))?;
+        let name = format!("--{}", stem);
+
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Cannot read template {}", path.display()))?;
+        let description = parse_description(&source).unwrap_or_default();
+
+        Ok(Template {
+            name,
+            description,
+            source: TemplateSource::File(path),
+        })
+    }
+
+    /// Extract the description of a template from its first doc line
+    ///
+    pub fn parse_description(source: &str) -> Option<String> {
+        let line = source.lines().next()?;
+        let line = line.strip_prefix("//!")?.trim();
+        if line.is_empty() {
+            None
+        } else {
+            Some(line.to_owned())
+        }
+    }
+
+    /// Format the list of templates as shown by `cargo wop new`
+    ///
+    pub fn format_template_list(templates: &[Template], env: &impl ExecutionEnv) -> String {
+        let mut res = String::new();
+        writeln!(res, "The following templates are available:").unwrap();
+        writeln!(res).unwrap();
+
+        for template in templates {
+            write!(res, "- \"{}\": {}", template.name, template.description).unwrap();
+            if let TemplateSource::File(path) = &template.source {
+                write!(res, " ({})", path.display()).unwrap();
+            }
+            writeln!(res).unwrap();
+        }
+
+        writeln!(res).unwrap();
+        writeln!(res, "Additional templates are loaded from:").unwrap();
+        writeln!(res).unwrap();
+        for dir in template_dirs(env) {
+            writeln!(res, "- {}", dir.display()).unwrap();
+        }
+        res
+    }
+
+    /// Render the template for the given target file
+    ///
+    /// Supported placeholders are `NAME`, `CRATE_NAME`, `AUTHOR`, `DATE`,
+    /// `EDITION` and any user supplied variables. User supplied variables
+    /// take precedence.
+    ///
+    pub fn render_template(
+        template: &str,
+        target: &Path,
+        vars: &[(String, String)],
+    ) -> Result<String> {
+        let name = || -> Result<String> {
+            let res = target
+                .file_stem()
+                .ok_or_else(|| anyhow!("Cannot get file stem"))?
+                .to_str()
+                .ok_or_else(|| anyhow!("Cannot get uf8 name"))?
+                .to_owned();
+            Ok(res)
+        };
+
+        let repl = |key: &str| -> Result<String> {
+            if let Some((_, value)) = vars.iter().rev().find(|(k, _)| k == key) {
+                return Ok(value.clone());
+            }
+
+            match key {
+                "NAME" => name(),
+                "CRATE_NAME" => Ok(name()?.replace('-', "_")),
+                "AUTHOR" => find_author(),
+                "DATE" => Ok(super::util::format_date(std::time::SystemTime::now())),
+                "EDITION" => Ok(DEFAULT_EDITION.to_owned()),
+                _ => bail!("Unknown pattern {}", key),
+            }
+        };
+
+        format_dynamic(template, repl)
+    }
+
+    /// Determine the author similar to `cargo new`
+    ///
+    fn find_author() -> Result<String> {
+        for var in &["CARGO_NAME", "GIT_AUTHOR_NAME", "GIT_COMMITTER_NAME"] {
+            if let Ok(name) = std::env::var(var) {
+                return Ok(name);
+            }
+        }
+
+        let output = std::process::Command::new("git")
+            .args(["config", "user.name"])
+            .output();
+        if let Ok(output) = output {
+            let name = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            if output.status.success() && !name.is_empty() {
+                return Ok(name);
+            }
+        }
+
+        for var in &["USER", "USERNAME", "NAME"] {
+            if let Ok(name) = std::env::var(var) {
+                return Ok(name);
+            }
+        }
+
+        bail!("Could not determine the author, pass it as --var AUTHOR=...")
+    }
+}
+
+mod wheel {
+    //! Python wheels containing a single extension module
+    //!
+    //! The wheel is written as an uncompressed zip archive.
+    //!
+    use std::{fmt::Write as _, fs::File, io::Write as _, path::Path};
+
+    use anyhow::{bail, Result};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+    use sha2::{Digest, Sha256};
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    /// The script printing the tags and the extension suffix of an interpreter
+    ///
+    pub const PYTHON_TAGS_SCRIPT: &str = r#"
+import sys, sysconfig
+name = sys.implementation.name
+impl = {"cpython": "cp", "pypy": "pp"}.get(name, name)
+python = impl + "%d%d" % sys.version_info[:2]
+if name == "cpython":
+    abi = python + ("t" if sysconfig.get_config_var("Py_GIL_DISABLED") else "")
+else:
+    abi = (sysconfig.get_config_var("SOABI") or "none").replace("-", "_").replace(".", "_")
+platform = sysconfig.get_platform().replace("-", "_").replace(".", "_")
+print(python, abi, platform, sysconfig.get_config_var("EXT_SUFFIX"))
+"#;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct WheelTags {
+        pub python: String,
+        pub abi: String,
+        pub platform: String,
+    }
+
+    impl WheelTags {
+        /// Parse the output of [PYTHON_TAGS_SCRIPT] into the tags and the extension suffix
+        ///
+        pub fn parse(output: &str) -> Result<(Self, String)> {
+            let parts = output.split_whitespace().collect::<Vec<_>>();
+            if parts.len() != 4 {
+                bail!("Invalid python tags: {}", output.trim());
+            }
+            let tags = Self {
+                python: parts[0].to_owned(),
+                abi: parts[1].to_owned(),
+                platform: parts[2].to_owned(),
+            };
+            Ok((tags, parts[3].to_owned()))
+        }
+
+        /// The tags for the given python tag and the host platform
+        ///
+        pub fn from_python_tag(python: &str) -> Self {
+            Self {
+                python: python.to_owned(),
+                abi: python.to_owned(),
+                platform: host_platform_tag(),
+            }
+        }
+    }
+
+    /// The platform tag of the host, used if no interpreter is queried
+    ///
+    pub fn host_platform_tag() -> String {
+        use std::env::consts::{ARCH, OS};
+        match (OS, ARCH) {
+            ("windows", "x86_64") => String::from("win_amd64"),
+            ("windows", "x86") => String::from("win32"),
+            ("windows", "aarch64") => String::from("win_arm64"),
+            ("macos", "aarch64") => String::from("macosx_11_0_arm64"),
+            ("macos", arch) => format!("macosx_10_12_{}", arch),
+            (os, arch) => format!("{}_{}", os, arch),
+        }
+    }
+
+    /// The suffix of extension modules of the host, used if no interpreter is queried
+    ///
+    pub fn host_extension_suffix() -> &'static str {
+        if cfg!(windows) {
+            ".pyd"
+        } else {
+            ".so"
+        }
+    }
+
+    /// The file name of a wheel, the tags are given as `PYTHON-ABI-PLATFORM`
+    ///
+    pub fn wheel_file_name(name: &str, version: &str, tags: &str) -> Result<String> {
+        Ok(format!(
+            "{}-{}-{}.whl",
+            escape_name(name),
+            python_version(version)?,
+            tags
+        ))
+    }
+
+    /// A wheel with its metadata and the files to include
+    ///
+    #[derive(Debug)]
+    pub struct Wheel {
+        pub name: String,
+        pub version: String,
+        pub summary: Option<String>,
+        pub tags: WheelTags,
+        /// The files as (path inside the wheel, content)
+        pub files: Vec<(String, Vec<u8>)>,
+    }
+
+    impl Wheel {
+        pub fn file_name(&self) -> Result<String> {
+            let tags = format!(
+                "{}-{}-{}",
+                self.tags.python, self.tags.abi, self.tags.platform
+            );
+            wheel_file_name(&self.name, &self.version, &tags)
+        }
+
+        /// Write the wheel including the generated metadata
+        ///
+        pub fn write(&self, path: &Path) -> Result<()> {
+            let dist_info = format!(
+                "{}-{}.dist-info",
+                escape_name(&self.name),
+                python_version(&self.version)?
+            );
+
+            let mut files = self.files.clone();
+            files.push((
+                format!("{}/METADATA", dist_info),
+                self.metadata()?.into_bytes(),
+            ));
+            files.push((
+                format!("{}/WHEEL", dist_info),
+                self.wheel_info().into_bytes(),
+            ));
+
+            let mut record = String::new();
+            for (name, content) in &files {
+                let hash = record_hash(content);
+                writeln!(record, "{},sha256={},{}", name, hash, content.len()).unwrap();
+            }
+            writeln!(record, "{}/RECORD,,", dist_info).unwrap();
+            files.push((format!("{}/RECORD", dist_info), record.into_bytes()));
+
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Stored)
+                .unix_permissions(0o644);
+            let mut zip = ZipWriter::new(File::create(path)?);
+            for (name, content) in &files {
+                zip.start_file(name.as_str(), options)?;
+                zip.write_all(content)?;
+            }
+            zip.finish()?;
+            Ok(())
+        }
+
+        fn metadata(&self) -> Result<String> {
+            let mut res = String::new();
+            writeln!(res, "Metadata-Version: 2.1").unwrap();
+            writeln!(res, "Name: {}", self.name).unwrap();
+            writeln!(res, "Version: {}", python_version(&self.version)?).unwrap();
+            if let Some(summary) = self.summary.as_ref() {
+                writeln!(
+                    res,
+                    "Summary: {}",
+                    summary.lines().next().unwrap_or_default()
+                )
+                .unwrap();
+            }
+            Ok(res)
+        }
+
+        fn wheel_info(&self) -> String {
+            let mut res = String::new();
+            writeln!(res, "Wheel-Version: 1.0").unwrap();
+            writeln!(res, "Generator: cargo-wop {}", env!("CARGO_PKG_VERSION")).unwrap();
+            writeln!(res, "Root-Is-Purelib: false").unwrap();
+            writeln!(
+                res,
+                "Tag: {}-{}-{}",
+                self.tags.python, self.tags.abi, self.tags.platform
+            )
+            .unwrap();
+            res
+        }
+    }
+
+    /// Escape the distribution name for use in file names
+    ///
+    pub fn escape_name(name: &str) -> String {
+        let mut res = String::new();
+        for c in name.chars() {
+            if c == '-' || c == '_' || c == '.' {
+                if !res.ends_with('_') {
+                    res.push('_');
+                }
+            } else {
+                res.push(c.to_ascii_lowercase());
+            }
+        }
+        res
+    }
+
+    /// Translate a cargo version into a Python version
+    ///
+    /// Pre-releases such as `1.0.0-alpha.1` are translated into `1.0.0a1`.
+    ///
+    pub fn python_version(version: &str) -> Result<String> {
+        let (release, pre) = match version.split_once('-') {
+            Some((release, pre)) => (release, Some(pre)),
+            None => (version, None),
+        };
+        if version.contains('+') {
+            bail!(
+                "Versions with build metadata are not supported: {}",
+                version
+            );
+        }
+
+        let pre = match pre {
+            Some(pre) => pre,
+            None => return Ok(release.to_owned()),
+        };
+        let (kind, number) = pre.split_once('.').unwrap_or((pre, "0"));
+        let kind = match kind {
+            "alpha" | "a" => "a",
+            "beta" | "b" => "b",
+            "rc" => "rc",
+            _ => bail!(
+                "Cannot translate the version {} into a Python version",
+                version
+            ),
+        };
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            bail!(
+                "Cannot translate the version {} into a Python version",
+                version
+            );
+        }
+        Ok(format!("{}{}{}", release, kind, number))
+    }
+
+    /// The hash of a file as listed in RECORD, URL-safe base64 without padding
+    ///
+    pub fn record_hash(content: &[u8]) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(content))
+    }
+}
+
+mod c_header {
+    //! A C header generator for the `extern "C"` interface of a script
+    //!
+    //! Only the subset of cbindgen required for typical scripts is supported:
+    //! functions and statics marked with `#[no_mangle]` or `#[export_name]`,
+    //! `#[repr(C)]` structs and fieldless enums with an explicit `repr`. Other
+    //! structs are declared as opaque types, i.e., they can only be used via
+    //! pointers. To keep the dependencies minimal, the source is tokenized
+    //! here instead of relying on syn.
+    //!
+    use std::{collections::HashSet, fmt::Write as _};
+
+    use anyhow::{anyhow, bail, ensure, Context, Result};
+
+    /// The supported subset of the cbindgen configuration
+    ///
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct HeaderConfig {
+        pub header: Option<String>,
+        pub trailer: Option<String>,
+        pub autogen_warning: Option<String>,
+        pub include_guard: Option<String>,
+        pub pragma_once: bool,
+        pub no_includes: bool,
+        pub sys_includes: Vec<String>,
+        pub includes: Vec<String>,
+        pub after_includes: Option<String>,
+        pub cpp_compat: bool,
+        pub documentation: bool,
+    }
+
+    impl Default for HeaderConfig {
+        fn default() -> Self {
+            Self {
+                header: None,
+                trailer: None,
+                autogen_warning: None,
+                include_guard: None,
+                pragma_once: false,
+                no_includes: false,
+                sys_includes: Vec::new(),
+                includes: Vec::new(),
+                after_includes: None,
+                cpp_compat: false,
+                documentation: true,
+            }
+        }
+    }
+
+    /// Generate the header for the exported items of the given source
+    ///
+    pub fn generate_header(source: &str, config: &HeaderConfig) -> Result<String> {
+        let items = Parser::new(tokenize(source)?).parse_items()?;
+        let mut generator = Generator::new(&items, config);
+
+        let mut types = String::new();
+        for item in sort_definitions(&items) {
+            match item {
+                Item::Struct(item) => types.push_str(&generator.struct_definition(item)?),
+                Item::Enum(item) => types.push_str(&generator.enum_definition(item)),
+                _ => {}
+            }
+        }
+
+        let mut declarations = Vec::new();
+        for item in &items {
+            match item {
+                Item::Function(item) => declarations.push(
+                    generator
+                        .function_declaration(item)
+                        .with_context(|| format!("Cannot declare function {}", item.name))?,
+                ),
+                Item::Static(item) => declarations.push(
+                    generator
+                        .static_declaration(item)
+                        .with_context(|| format!("Cannot declare static {}", item.name))?,
+                ),
+                Item::Struct(_) | Item::Enum(_) => {}
+            }
+        }
+
+        let mut result = String::new();
+        if let Some(header) = &config.header {
+            writeln!(result, "{}\n", header)?;
+        }
+        if let Some(guard) = &config.include_guard {
+            writeln!(result, "#ifndef {}\n#define {}\n", guard, guard)?;
+        } else if config.pragma_once {
+            writeln!(result, "#pragma once\n")?;
+        }
+        if let Some(warning) = &config.autogen_warning {
+            writeln!(result, "{}\n", warning)?;
+        }
+
+        let mut has_includes = false;
+        if !config.no_includes {
+            for include in ["stdarg.h", "stdbool.h", "stdint.h", "stdlib.h"] {
+                writeln!(result, "#include <{}>", include)?;
+            }
+            has_includes = true;
+        }
+        for include in &config.sys_includes {
+            writeln!(result, "#include <{}>", include)?;
+            has_includes = true;
+        }
+        for include in &config.includes {
+            writeln!(result, "#include \"{}\"", include)?;
+            has_includes = true;
+        }
+        if has_includes {
+            result.push('\n');
+        }
+        if let Some(after_includes) = &config.after_includes {
+            writeln!(result, "{}\n", after_includes)?;
+        }
+
+        for name in &generator.opaque {
+            writeln!(result, "typedef struct {} {};\n", name, name)?;
+        }
+        result.push_str(&types);
+
+        if config.cpp_compat {
+            result.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif  // __cplusplus\n\n");
+        }
+        for declaration in declarations {
+            writeln!(result, "{}", declaration)?;
+        }
+        if config.cpp_compat {
+            result.push_str("#ifdef __cplusplus\n}  // extern \"C\"\n#endif  // __cplusplus\n\n");
+        }
+
+        if let Some(guard) = &config.include_guard {
+            writeln!(result, "#endif  /* {} */", guard)?;
+        }
+        if let Some(trailer) = &config.trailer {
+            writeln!(result, "\n{}", trailer)?;
+        }
+        Ok(result)
+    }
+
+    /// The structs and enums with a C definition, each after the types it uses
+    ///
+    /// Apart from that the source order is kept. Cycles are only possible via
+    /// pointers and are not resolved.
+    ///
+    fn sort_definitions(items: &[Item]) -> Vec<&Item> {
+        let definitions = items
+            .iter()
+            .filter(|item| match item {
+                Item::Struct(item) => item.repr_c && !item.fields.is_empty(),
+                Item::Enum(_) => true,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+
+        let mut visited = HashSet::new();
+        let mut result = Vec::new();
+        for item in &definitions {
+            visit_definition(item, &definitions, &mut visited, &mut result);
+        }
+        result
+    }
+
+    fn visit_definition<'a>(
+        item: &'a Item,
+        definitions: &[&'a Item],
+        visited: &mut HashSet<&'a str>,
+        result: &mut Vec<&'a Item>,
+    ) {
+        let name = match item {
+            Item::Struct(item) => item.name.as_str(),
+            Item::Enum(item) => item.name.as_str(),
+            _ => return,
+        };
+        if !visited.insert(name) {
+            return;
+        }
+
+        if let Item::Struct(item) = item {
+            let mut used = Vec::new();
+            for (_, ty, _) in &item.fields {
+                used_types(ty, &mut used);
+            }
+            for used in used {
+                let dependency = definitions.iter().find(|other| match other {
+                    Item::Struct(other) => other.name == used,
+                    Item::Enum(other) => other.name == used,
+                    _ => false,
+                });
+                if let Some(dependency) = dependency {
+                    visit_definition(dependency, definitions, visited, result);
+                }
+            }
+        }
+        result.push(item);
+    }
+
+    /// Collect the names of all types referenced by the given type
+    ///
+    fn used_types<'a>(ty: &'a Type, result: &mut Vec<&'a str>) {
+        match ty {
+            Type::Path(name, args) => {
+                result.push(name);
+                for arg in args {
+                    used_types(arg, result);
+                }
+            }
+            Type::Pointer { pointee, .. } => used_types(pointee, result),
+            Type::Array(element, _) => used_types(element, result),
+            Type::Function { params, output } => {
+                for param in params {
+                    used_types(param, result);
+                }
+                used_types(output, result);
+            }
+            Type::Unit | Type::Never => {}
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Token {
+        Ident(String),
+        Punct(char),
+        /// A literal, for strings the content without quotes
+        Literal(String),
+        Lifetime(String),
+        /// An outer doc comment (`/// ...`)
+        Doc(String),
+    }
+
+    fn tokenize(source: &str) -> Result<Vec<Token>> {
+        let chars = source.chars().collect::<Vec<_>>();
+        let at = |pos: usize| chars.get(pos).copied().unwrap_or('\0');
+        let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < chars.len() {
+            let c = chars[pos];
+            if c.is_whitespace() {
+                pos += 1;
+            } else if c == '/' && at(pos + 1) == '/' {
+                let end = (pos..chars.len())
+                    .find(|&idx| chars[idx] == '\n')
+                    .unwrap_or(chars.len());
+                let line = chars[pos..end].iter().collect::<String>();
+                if line.starts_with("///") && !line.starts_with("////") {
+                    let doc = &line[3..];
+                    let doc = doc.strip_prefix(' ').unwrap_or(doc);
+                    tokens.push(Token::Doc(doc.trim_end().to_owned()));
+                }
+                pos = end;
+            } else if c == '/' && at(pos + 1) == '*' {
+                let mut depth = 0;
+                loop {
+                    ensure!(pos < chars.len(), "Unterminated block comment");
+                    if at(pos) == '/' && at(pos + 1) == '*' {
+                        depth += 1;
+                        pos += 2;
+                    } else if at(pos) == '*' && at(pos + 1) == '/' {
+                        depth -= 1;
+                        pos += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        pos += 1;
+                    }
+                }
+            } else if c == '"' || (c == 'b' && at(pos + 1) == '"') {
+                let start = if c == '"' { pos + 1 } else { pos + 2 };
+                let mut end = start;
+                while at(end) != '"' {
+                    ensure!(end < chars.len(), "Unterminated string literal");
+                    end += if at(end) == '\\' { 2 } else { 1 };
+                }
+                tokens.push(Token::Literal(chars[start..end].iter().collect()));
+                pos = end + 1;
+            } else if (c == 'r' || (c == 'b' && at(pos + 1) == 'r'))
+                && matches!(at(pos + 1), '"' | '#' | 'r')
+                && raw_string_start(&chars, pos).is_some()
+            {
+                let (start, hashes) = raw_string_start(&chars, pos).unwrap_or_default();
+                let terminator = format!("\"{}", "#".repeat(hashes))
+                    .chars()
+                    .collect::<Vec<_>>();
+                let end = (start..chars.len())
+                    .find(|&idx| chars[idx..].starts_with(&terminator))
+                    .ok_or_else(|| anyhow!("Unterminated raw string literal"))?;
+                tokens.push(Token::Literal(chars[start..end].iter().collect()));
+                pos = end + terminator.len();
+            } else if c == '\'' || (c == 'b' && at(pos + 1) == '\'') {
+                let start = if c == '\'' { pos + 1 } else { pos + 2 };
+                if at(start) != '\\' && at(start + 1) != '\'' {
+                    let end = (start..chars.len())
+                        .find(|&idx| !is_ident_char(chars[idx]))
+                        .unwrap_or(chars.len());
+                    tokens.push(Token::Lifetime(chars[start..end].iter().collect()));
+                    pos = end;
+                } else {
+                    let mut end = start;
+                    while at(end) != '\'' {
+                        ensure!(end < chars.len(), "Unterminated character literal");
+                        end += if at(end) == '\\' { 2 } else { 1 };
+                    }
+                    tokens.push(Token::Literal(chars[start..end].iter().collect()));
+                    pos = end + 1;
+                }
+            } else if c == 'r' && at(pos + 1) == '#' && is_ident_char(at(pos + 2)) {
+                let end = (pos + 2..chars.len())
+                    .find(|&idx| !is_ident_char(chars[idx]))
+                    .unwrap_or(chars.len());
+                tokens.push(Token::Ident(chars[pos + 2..end].iter().collect()));
+                pos = end;
+            } else if is_ident_char(c) {
+                let end = (pos..chars.len())
+                    .find(|&idx| !is_ident_char(chars[idx]))
+                    .unwrap_or(chars.len());
+                let text = chars[pos..end].iter().collect::<String>();
+                if c.is_ascii_digit() {
+                    tokens.push(Token::Literal(text));
+                } else {
+                    tokens.push(Token::Ident(text));
+                }
+                pos = end;
+            } else {
+                tokens.push(Token::Punct(c));
+                pos += 1;
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// The start of the content and the number of hashes of a raw string
+    ///
+    fn raw_string_start(chars: &[char], pos: usize) -> Option<(usize, usize)> {
+        let mut pos = pos + if chars[pos] == 'b' { 2 } else { 1 };
+        let mut hashes = 0;
+        while chars.get(pos) == Some(&'#') {
+            hashes += 1;
+            pos += 1;
+        }
+        (chars.get(pos) == Some(&'"')).then_some((pos + 1, hashes))
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Type {
+        /// The last segment of a path with its generic arguments
+        Path(String, Vec<Type>),
+        Pointer {
+            mutable: bool,
+            pointee: Box<Type>,
+        },
+        Array(Box<Type>, String),
+        /// A function pointer with the C ABI
+        Function {
+            params: Vec<Type>,
+            output: Box<Type>,
+        },
+        Unit,
+        Never,
+    }
+
+    #[derive(Debug)]
+    enum Item {
+        Function(Function),
+        Static(Static),
+        Struct(Struct),
+        Enum(Enum),
+    }
+
+    /// A field as (name, type, docs)
+    type Field = (String, Type, Vec<String>);
+
+    /// An enum variant as (name, discriminant, docs)
+    type Variant = (String, Option<String>, Vec<String>);
+
+    #[derive(Debug)]
+    struct Function {
+        name: String,
+        docs: Vec<String>,
+        params: Vec<(String, Type)>,
+        output: Type,
+    }
+
+    #[derive(Debug)]
+    struct Static {
+        name: String,
+        docs: Vec<String>,
+        mutable: bool,
+        ty: Type,
+    }
+
+    #[derive(Debug)]
+    struct Struct {
+        name: String,
+        docs: Vec<String>,
+        repr_c: bool,
+        /// The fields, only parsed for `#[repr(C)]` structs
+        fields: Vec<Field>,
+    }
+
+    #[derive(Debug)]
+    struct Enum {
+        name: String,
+        docs: Vec<String>,
+        /// The integer type for `#[repr(u8)]`, ..., or `None` for `#[repr(C)]`
+        repr: Option<String>,
+        variants: Vec<Variant>,
+    }
+
+    struct Parser {
+        tokens: Vec<Token>,
+        pos: usize,
+    }
+
+    impl Parser {
+        fn new(tokens: Vec<Token>) -> Self {
+            Self { tokens, pos: 0 }
+        }
+
+        fn peek(&self) -> Option<&Token> {
+            self.tokens.get(self.pos)
+        }
+
+        fn next(&mut self) -> Option<Token> {
+            let result = self.tokens.get(self.pos).cloned();
+            self.pos += 1;
+            result
+        }
+
+        fn is_punct(&self, c: char) -> bool {
+            self.peek() == Some(&Token::Punct(c))
+        }
+
+        fn is_ident(&self, ident: &str) -> bool {
+            matches!(self.peek(), Some(Token::Ident(value)) if value == ident)
+        }
+
+        fn eat_punct(&mut self, c: char) -> bool {
+            let result = self.is_punct(c);
+            if result {
+                self.pos += 1;
+            }
+            result
+        }
+
+        fn eat_ident(&mut self, ident: &str) -> bool {
+            let result = self.is_ident(ident);
+            if result {
+                self.pos += 1;
+            }
+            result
+        }
+
+        fn expect_punct(&mut self, c: char) -> Result<()> {
+            ensure!(
+                self.eat_punct(c),
+                "Expected {:?}, found {:?}",
+                c,
+                self.peek()
+            );
+            Ok(())
+        }
+
+        fn expect_ident(&mut self) -> Result<String> {
+            match self.next() {
+                Some(Token::Ident(ident)) => Ok(ident),
+                token => bail!("Expected an identifier, found {:?}", token),
+            }
+        }
+
+        /// Skip a group starting at an opening bracket, including its end
+        ///
+        fn skip_group(&mut self) -> Result<()> {
+            let mut depth = 0;
+            loop {
+                match self.next() {
+                    Some(Token::Punct('(' | '[' | '{')) => depth += 1,
+                    Some(Token::Punct(')' | ']' | '}')) => depth -= 1,
+                    Some(_) => {}
+                    None => bail!("Unbalanced brackets"),
+                }
+                if depth == 0 {
+                    return Ok(());
+                }
+            }
+        }
+
+        /// Skip the remainder of an item, i.e., up to a `;` or a braced group
+        ///
+        fn skip_item(&mut self) -> Result<()> {
+            while let Some(token) = self.peek() {
+                match token {
+                    Token::Punct(';') => {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                    Token::Punct('{') => return self.skip_group(),
+                    Token::Punct('(' | '[') => self.skip_group()?,
+                    Token::Punct('}') => return Ok(()),
+                    _ => self.pos += 1,
+                }
+            }
+            Ok(())
+        }
+
+        /// Parse doc comments and outer attributes preceding an item
+        ///
+        fn parse_prefix(&mut self) -> Result<(Vec<String>, Vec<Vec<Token>>)> {
+            let mut docs = Vec::new();
+            let mut attributes = Vec::new();
+            loop {
+                match self.peek() {
+                    Some(Token::Doc(doc)) => {
+                        docs.push(doc.clone());
+                        self.pos += 1;
+                    }
+                    Some(Token::Punct('#')) => {
+                        self.pos += 1;
+                        let inner = self.eat_punct('!');
+                        let start = self.pos + 1;
+                        ensure!(self.is_punct('['), "Invalid attribute");
+                        self.skip_group()?;
+                        if !inner {
+                            attributes.push(self.tokens[start..self.pos - 1].to_vec());
+                        }
+                    }
+                    _ => return Ok((docs, attributes)),
+                }
+            }
+        }
+
+        fn parse_visibility(&mut self) -> Result<()> {
+            if self.eat_ident("pub") && self.is_punct('(') {
+                self.skip_group()?;
+            }
+            Ok(())
+        }
+
+        fn parse_items(&mut self) -> Result<Vec<Item>> {
+            let mut items = Vec::new();
+            while self.peek().is_some() {
+                let (docs, attributes) = self.parse_prefix()?;
+                self.parse_visibility()?;
+
+                let mut abi = None;
+                loop {
+                    if self.eat_ident("extern") {
+                        abi = Some(match self.peek() {
+                            Some(Token::Literal(abi)) => {
+                                let abi = abi.clone();
+                                self.pos += 1;
+                                abi
+                            }
+                            _ => String::from("C"),
+                        });
+                    } else if !(self.eat_ident("unsafe")
+                        || self.eat_ident("const")
+                        || self.eat_ident("async"))
+                    {
+                        break;
+                    }
+                }
+
+                let export_name = find_export_name(&attributes);
+                let is_c_abi = matches!(abi.as_deref(), Some("C" | "C-unwind"));
+
+                match self.peek() {
+                    Some(Token::Ident(keyword)) if keyword == "fn" => {
+                        if let (Some(export_name), true) = (export_name, is_c_abi) {
+                            self.pos += 1;
+                            let name = self.expect_ident()?;
+                            let function = self
+                                .parse_function(export_name.unwrap_or(name.clone()), docs)
+                                .with_context(|| format!("Cannot parse function {}", name))?;
+                            items.push(Item::Function(function));
+                        } else {
+                            self.skip_item()?;
+                        }
+                    }
+                    Some(Token::Ident(keyword)) if keyword == "static" => {
+                        if let Some(export_name) = export_name {
+                            self.pos += 1;
+                            let mutable = self.eat_ident("mut");
+                            let name = self.expect_ident()?;
+                            self.expect_punct(':')?;
+                            let ty = self
+                                .parse_type()
+                                .with_context(|| format!("Cannot parse static {}", name))?;
+                            items.push(Item::Static(Static {
+                                name: export_name.unwrap_or(name),
+                                docs,
+                                mutable,
+                                ty,
+                            }));
+                        }
+                        self.skip_item()?;
+                    }
+                    Some(Token::Ident(keyword)) if keyword == "struct" => {
+                        self.pos += 1;
+                        let name = self.expect_ident()?;
+                        let generic = self.parse_generics()?;
+                        let repr = find_repr(&attributes);
+                        let repr_c = repr.iter().any(|repr| repr == "C") && !generic;
+                        let fields = if repr_c {
+                            self.parse_fields()
+                                .with_context(|| format!("Cannot parse struct {}", name))?
+                        } else {
+                            self.skip_item()?;
+                            Vec::new()
+                        };
+                        items.push(Item::Struct(Struct {
+                            name,
+                            docs,
+                            repr_c,
+                            fields,
+                        }));
+                    }
+                    Some(Token::Ident(keyword)) if keyword == "enum" => {
+                        self.pos += 1;
+                        let name = self.expect_ident()?;
+                        let repr = find_repr(&attributes);
+                        let variants = if repr.is_empty() || !self.is_punct('{') {
+                            None
+                        } else {
+                            self.parse_variants()
+                                .with_context(|| format!("Cannot parse enum {}", name))?
+                        };
+                        match variants {
+                            Some(variants) => items.push(Item::Enum(Enum {
+                                name,
+                                docs,
+                                repr: repr.into_iter().find(|repr| repr != "C"),
+                                variants,
+                            })),
+                            None => self.skip_item()?,
+                        }
+                    }
+                    Some(Token::Ident(keyword)) if keyword == "mod" => {
+                        // the items of inline modules are parsed as part of the outer items
+                        self.pos += 1;
+                        self.expect_ident()?;
+                        if !self.eat_punct('{') {
+                            self.skip_item()?;
+                        }
+                    }
+                    Some(Token::Punct('}' | ';')) => self.pos += 1,
+                    _ => self.skip_item()?,
+                }
+            }
+            Ok(items)
+        }
+
+        /// Skip generic parameters, returns whether there are any besides lifetimes
+        ///
+        fn parse_generics(&mut self) -> Result<bool> {
+            let mut generic = false;
+            if self.eat_punct('<') {
+                while !self.eat_punct('>') {
+                    match self.next() {
+                        Some(Token::Lifetime(_) | Token::Punct(',' | ':' | '+')) => {}
+                        Some(_) => generic = true,
+                        None => bail!("Unterminated generics"),
+                    }
+                }
+            }
+            Ok(generic)
+        }
+
+        fn parse_function(&mut self, name: String, docs: Vec<String>) -> Result<Function> {
+            ensure!(!self.is_punct('<'), "Generic functions are not supported");
+            self.expect_punct('(')?;
+
+            let mut params = Vec::new();
+            while !self.eat_punct(')') {
+                self.parse_prefix()?;
+                ensure!(!self.is_punct('.'), "Variadic functions are not supported");
+
+                let mut param_name = String::from("_");
+                while !self.is_punct(':') {
+                    match self.next() {
+                        Some(Token::Ident(ident)) if ident != "mut" => param_name = ident,
+                        Some(Token::Ident(_)) => {}
+                        token => bail!("Unsupported parameter pattern {:?}", token),
+                    }
+                }
+                self.expect_punct(':')?;
+                params.push((param_name, self.parse_type()?));
+                if !self.eat_punct(',') {
+                    self.expect_punct(')')?;
+                    break;
+                }
+            }
+
+            let output = if self.eat_punct('-') {
+                self.expect_punct('>')?;
+                self.parse_type()?
+            } else {
+                Type::Unit
+            };
+            self.skip_item()?;
+
+            Ok(Function {
+                name,
+                docs,
+                params,
+                output,
+            })
+        }
 
-            state = match (state, line_start) {
-                (ParseState::Start, LineStart::Other) => {
-                    return Ok(Value::Table(Default::default()))
+        fn parse_fields(&mut self) -> Result<Vec<Field>> {
+            let mut fields = Vec::new();
+            if self.eat_punct('{') {
+                while !self.eat_punct('}') {
+                    let (docs, _) = self.parse_prefix()?;
+                    self.parse_visibility()?;
+                    let name = self.expect_ident()?;
+                    self.expect_punct(':')?;
+                    fields.push((name, self.parse_type()?, docs));
+                    if !self.eat_punct(',') {
+                        self.expect_punct('}')?;
+                        break;
+                    }
                 }
-                (ParseState::DocComment, LineStart::Other) => {
-                    return Ok(Value::Table(Default::default()))
+            } else if self.eat_punct('(') {
+                while !self.eat_punct(')') {
+                    let (docs, _) = self.parse_prefix()?;
+                    self.parse_visibility()?;
+                    let name = format!("_{}", fields.len());
+                    fields.push((name, self.parse_type()?, docs));
+                    if !self.eat_punct(',') {
+                        self.expect_punct(')')?;
+                        break;
+                    }
                 }
-                (ParseState::Start, LineStart::DocComment) => ParseState::DocComment,
-                (ParseState::Start, LineStart::ManifestEnd) => state,
-                (ParseState::DocComment, LineStart::DocComment) => state,
-                (ParseState::DocComment, LineStart::ManifestEnd) => state,
-                (ParseState::Start, LineStart::ManifestStart) => ParseState::Manifest,
-                (ParseState::DocComment, LineStart::ManifestStart) => ParseState::Manifest,
+                self.expect_punct(';')?;
+            } else {
+                self.expect_punct(';')?;
+            }
+            Ok(fields)
+        }
 
-                (ParseState::Manifest, LineStart::DocComment) => {
-                    let line = line.strip_prefix("//!").unwrap().trim_start();
-                    result.push_str(line);
-                    result.push('\n');
-                    state
+        /// Parse the variants of a fieldless enum, `None` for enums with fields
+        ///
+        fn parse_variants(&mut self) -> Result<Option<Vec<Variant>>> {
+            let start = self.pos;
+            self.expect_punct('{')?;
+
+            let mut variants = Vec::new();
+            while !self.eat_punct('}') {
+                let (docs, _) = self.parse_prefix()?;
+                let name = self.expect_ident()?;
+                if self.is_punct('(') || self.is_punct('{') {
+                    self.pos = start;
+                    return Ok(None);
                 }
-                (ParseState::Manifest, LineStart::ManifestEnd) => {
-                    return Ok(toml::from_str(&result)?)
+
+                let discriminant = if self.eat_punct('=') {
+                    let mut value = String::new();
+                    while !self.is_punct(',') && !self.is_punct('}') {
+                        match self.next() {
+                            Some(Token::Ident(text) | Token::Literal(text)) => {
+                                if value.ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                                    value.push(' ');
+                                }
+                                value.push_str(&text);
+                            }
+                            Some(Token::Punct(c)) => value.push(c),
+                            token => bail!("Unsupported discriminant {:?}", token),
+                        }
+                    }
+                    Some(value)
+                } else {
+                    None
+                };
+                variants.push((name, discriminant, docs));
+
+                if !self.eat_punct(',') {
+                    self.expect_punct('}')?;
+                    break;
                 }
-                (ParseState::Manifest, LineStart::ManifestStart) => bail!("Invalid manifest"),
-                (ParseState::Manifest, LineStart::Other) => bail!("Invalid manifest"),
+            }
+            Ok(Some(variants))
+        }
+
+        fn parse_type(&mut self) -> Result<Type> {
+            match self.next() {
+                Some(Token::Punct('*')) => {
+                    let mutable = self.eat_ident("mut");
+                    ensure!(
+                        mutable || self.eat_ident("const"),
+                        "Raw pointers must be const or mut"
+                    );
+                    Ok(Type::Pointer {
+                        mutable,
+                        pointee: Box::new(self.parse_type()?),
+                    })
+                }
+                Some(Token::Punct('&')) => {
+                    if let Some(Token::Lifetime(_)) = self.peek() {
+                        self.pos += 1;
+                    }
+                    let mutable = self.eat_ident("mut");
+                    Ok(Type::Pointer {
+                        mutable,
+                        pointee: Box::new(self.parse_type()?),
+                    })
+                }
+                Some(Token::Punct('[')) => {
+                    let element = self.parse_type()?;
+                    ensure!(self.eat_punct(';'), "Slices are not supported");
+                    let mut len = String::new();
+                    while !self.eat_punct(']') {
+                        match self.next() {
+                            Some(Token::Ident(text) | Token::Literal(text)) => len.push_str(&text),
+                            Some(Token::Punct(c)) => len.push(c),
+                            token => bail!("Unsupported array length {:?}", token),
+                        }
+                    }
+                    Ok(Type::Array(Box::new(element), len))
+                }
+                Some(Token::Punct('(')) => {
+                    ensure!(self.eat_punct(')'), "Tuples are not supported");
+                    Ok(Type::Unit)
+                }
+                Some(Token::Punct('!')) => Ok(Type::Never),
+                Some(Token::Punct(':')) => {
+                    self.expect_punct(':')?;
+                    self.parse_type()
+                }
+                Some(Token::Ident(ident))
+                    if matches!(ident.as_str(), "fn" | "extern" | "unsafe") =>
+                {
+                    self.pos -= 1;
+                    self.parse_function_pointer()
+                }
+                Some(Token::Ident(ident)) => {
+                    ensure!(
+                        !matches!(ident.as_str(), "dyn" | "impl" | "Self"),
+                        "Unsupported type starting with {}",
+                        ident
+                    );
+                    let mut name = ident;
+                    while self.is_punct(':') {
+                        self.expect_punct(':')?;
+                        self.expect_punct(':')?;
+                        name = self.expect_ident()?;
+                    }
+
+                    let mut args = Vec::new();
+                    if self.eat_punct('<') {
+                        while !self.eat_punct('>') {
+                            if let Some(Token::Lifetime(_)) = self.peek() {
+                                self.pos += 1;
+                            } else {
+                                args.push(self.parse_type()?);
+                            }
+                            if !self.eat_punct(',') {
+                                self.expect_punct('>')?;
+                                break;
+                            }
+                        }
+                    }
+                    Ok(Type::Path(name, args))
+                }
+                token => bail!("Unsupported type starting with {:?}", token),
+            }
+        }
+
+        /// Parse a function pointer type, e.g., `extern "C" fn(i32) -> i32`
+        ///
+        fn parse_function_pointer(&mut self) -> Result<Type> {
+            self.eat_ident("unsafe");
+            let abi = if self.eat_ident("extern") {
+                match self.peek() {
+                    Some(Token::Literal(abi)) => {
+                        let abi = abi.clone();
+                        self.pos += 1;
+                        abi
+                    }
+                    _ => String::from("C"),
+                }
+            } else {
+                String::from("Rust")
+            };
+            ensure!(
+                matches!(abi.as_str(), "C" | "C-unwind"),
+                "Function pointers must use the C ABI, found {:?}",
+                abi
+            );
+            ensure!(self.eat_ident("fn"), "Expected fn in function pointer type");
+            self.expect_punct('(')?;
+
+            let mut params = Vec::new();
+            while !self.eat_punct(')') {
+                ensure!(!self.is_punct('.'), "Variadic functions are not supported");
+                // skip the optional parameter name
+                let is_named = matches!(self.peek(), Some(Token::Ident(_)))
+                    && self.tokens.get(self.pos + 1) == Some(&Token::Punct(':'))
+                    && self.tokens.get(self.pos + 2) != Some(&Token::Punct(':'));
+                if is_named {
+                    self.pos += 2;
+                }
+                params.push(self.parse_type()?);
+                if !self.eat_punct(',') {
+                    self.expect_punct(')')?;
+                    break;
+                }
+            }
+
+            let output = if self.is_punct('-') {
+                self.expect_punct('-')?;
+                self.expect_punct('>')?;
+                self.parse_type()?
+            } else {
+                Type::Unit
             };
+            Ok(Type::Function {
+                params,
+                output: Box::new(output),
+            })
         }
+    }
 
-        if state == ParseState::Manifest {
-            bail!("Incomplete manifest");
+    /// The exported name: `Some(None)` for `#[no_mangle]`, `Some(name)` for `#[export_name]`
+    ///
+    fn find_export_name(attributes: &[Vec<Token>]) -> Option<Option<String>> {
+        for attribute in attributes {
+            for (idx, token) in attribute.iter().enumerate() {
+                match token {
+                    Token::Ident(ident) if ident == "no_mangle" => return Some(None),
+                    Token::Ident(ident) if ident == "export_name" => {
+                        if let Some(Token::Literal(name)) = attribute.get(idx + 2) {
+                            return Some(Some(name.clone()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
         }
+        None
+    }
 
-        Ok(Value::Table(Default::default()))
+    /// The representations given via `#[repr(...)]`
+    ///
+    fn find_repr(attributes: &[Vec<Token>]) -> Vec<String> {
+        let mut result = Vec::new();
+        for attribute in attributes {
+            if attribute.first() != Some(&Token::Ident(String::from("repr"))) {
+                continue;
+            }
+            for token in &attribute[1..] {
+                if let Token::Ident(ident) = token {
+                    result.push(ident.clone());
+                }
+            }
+        }
+        result
     }
 
-    #[derive(Debug, PartialEq, Clone, Copy)]
-    enum ParseState {
-        Start,
-        DocComment,
-        Manifest,
+    /// The C type of builtin Rust types
+    ///
+    fn builtin_type(name: &str) -> Option<&'static str> {
+        let result = match name {
+            "i8" => "int8_t",
+            "i16" => "int16_t",
+            "i32" => "int32_t",
+            "i64" => "int64_t",
+            "isize" => "intptr_t",
+            "u8" => "uint8_t",
+            "u16" => "uint16_t",
+            "u32" => "uint32_t",
+            "u64" => "uint64_t",
+            "usize" => "uintptr_t",
+            "f32" | "c_float" => "float",
+            "f64" | "c_double" => "double",
+            "bool" => "bool",
+            "char" => "uint32_t",
+            "c_void" => "void",
+            "c_char" => "char",
+            "c_schar" => "signed char",
+            "c_uchar" => "unsigned char",
+            "c_short" => "short",
+            "c_ushort" => "unsigned short",
+            "c_int" => "int",
+            "c_uint" => "unsigned int",
+            "c_long" => "long",
+            "c_ulong" => "unsigned long",
+            "c_longlong" => "long long",
+            "c_ulonglong" => "unsigned long long",
+            _ => return None,
+        };
+        Some(result)
     }
 
-    #[derive(Debug, PartialEq, Clone, Copy)]
-    enum LineStart {
-        DocComment,
-        ManifestStart,
-        ManifestEnd,
-        Other,
+    struct Generator<'a> {
+        config: &'a HeaderConfig,
+        /// The structs and enums with a C compatible definition
+        defined: HashSet<&'a str>,
+        /// All other structs declared in the source
+        declared: HashSet<&'a str>,
+        /// The structs used as opaque types, in order of their first use
+        opaque: Vec<String>,
+    }
+
+    impl<'a> Generator<'a> {
+        fn new(items: &'a [Item], config: &'a HeaderConfig) -> Self {
+            let mut defined = HashSet::new();
+            let mut declared = HashSet::new();
+            for item in items {
+                match item {
+                    Item::Struct(item) if item.repr_c && !item.fields.is_empty() => {
+                        defined.insert(item.name.as_str());
+                    }
+                    Item::Struct(item) => {
+                        declared.insert(item.name.as_str());
+                    }
+                    Item::Enum(item) => {
+                        defined.insert(item.name.as_str());
+                    }
+                    _ => {}
+                }
+            }
+            Self {
+                config,
+                defined,
+                declared,
+                opaque: Vec::new(),
+            }
+        }
+
+        fn docs(&self, docs: &[String], indent: &str) -> String {
+            if !self.config.documentation || docs.is_empty() {
+                return String::new();
+            }
+            let mut result = format!("{}/**\n", indent);
+            for line in docs {
+                if line.is_empty() {
+                    result.push_str(&format!("{} *\n", indent));
+                } else {
+                    result.push_str(&format!("{} * {}\n", indent, line));
+                }
+            }
+            result.push_str(&format!("{} */\n", indent));
+            result
+        }
+
+        fn struct_definition(&mut self, item: &Struct) -> Result<String> {
+            let mut result = self.docs(&item.docs, "");
+            result.push_str(&format!("typedef struct {} {{\n", item.name));
+            for (name, ty, docs) in &item.fields {
+                let declaration = self
+                    .declaration(ty, name.clone(), false)
+                    .with_context(|| format!("Cannot declare field {}.{}", item.name, name))?;
+                result.push_str(&self.docs(docs, "  "));
+                result.push_str(&format!("  {};\n", declaration));
+            }
+            result.push_str(&format!("}} {};\n\n", item.name));
+            Ok(result)
+        }
+
+        /// Render an enum, enums with an integer repr use a typedef for the integer
+        ///
+        /// With `cpp_compat` the typedef is replaced by an enum base in C++,
+        /// as both cannot share the same name.
+        ///
+        fn enum_definition(&self, item: &Enum) -> String {
+            let repr = item.repr.as_deref().and_then(builtin_type);
+            let mut result = self.docs(&item.docs, "");
+            match repr {
+                Some(repr) if self.config.cpp_compat => result.push_str(&format!(
+                    "#ifdef __cplusplus\nenum {} : {} {{\n#else\nenum {} {{\n#endif  // __cplusplus\n",
+                    item.name, repr, item.name
+                )),
+                Some(_) => result.push_str(&format!("enum {} {{\n", item.name)),
+                None => result.push_str(&format!("typedef enum {} {{\n", item.name)),
+            }
+            for (name, discriminant, docs) in &item.variants {
+                result.push_str(&self.docs(docs, "  "));
+                match discriminant {
+                    Some(discriminant) => {
+                        result.push_str(&format!("  {} = {},\n", name, discriminant))
+                    }
+                    None => result.push_str(&format!("  {},\n", name)),
+                }
+            }
+            match repr {
+                Some(repr) if self.config.cpp_compat => result.push_str(&format!(
+                    "}};\n#ifndef __cplusplus\ntypedef {} {};\n#endif  // __cplusplus\n\n",
+                    repr, item.name
+                )),
+                Some(repr) => result.push_str(&format!("}};\ntypedef {} {};\n\n", repr, item.name)),
+                None => result.push_str(&format!("}} {};\n\n", item.name)),
+            }
+            result
+        }
+
+        fn function_declaration(&mut self, item: &Function) -> Result<String> {
+            let mut params = Vec::new();
+            for (name, ty) in &item.params {
+                params.push(self.declaration(ty, name.clone(), false)?);
+            }
+            let params = if params.is_empty() {
+                String::from("void")
+            } else {
+                params.join(", ")
+            };
+
+            let declarator = format!("{}({})", item.name, params);
+            let declaration = match &item.output {
+                Type::Unit | Type::Never => format!("void {}", declarator),
+                output => self.declaration(output, declarator, false)?,
+            };
+            Ok(format!("{}{};\n", self.docs(&item.docs, ""), declaration))
+        }
+
+        fn static_declaration(&mut self, item: &Static) -> Result<String> {
+            let declaration = self.declaration(&item.ty, item.name.clone(), !item.mutable)?;
+            Ok(format!(
+                "{}extern {};\n",
+                self.docs(&item.docs, ""),
+                declaration
+            ))
+        }
+
+        /// Render a C declaration of `declarator` with the given type
+        ///
+        fn declaration(&mut self, ty: &Type, declarator: String, is_const: bool) -> Result<String> {
+            let qualifier = if is_const { "const " } else { "" };
+            match ty {
+                Type::Pointer { mutable, pointee } => {
+                    self.declaration(pointee, format!("*{}{}", qualifier, declarator), !mutable)
+                }
+                Type::Array(element, len) => {
+                    let declarator = if declarator.starts_with('*') {
+                        format!("({})[{}]", declarator, len)
+                    } else {
+                        format!("{}[{}]", declarator, len)
+                    };
+                    self.declaration(element, declarator, is_const)
+                }
+                Type::Path(name, args) => match (name.as_str(), args.as_slice()) {
+                    ("Option", [pointer @ (Type::Pointer { .. } | Type::Function { .. })]) => {
+                        self.declaration(pointer, declarator, is_const)
+                    }
+                    ("Option", [Type::Path(inner, inner_args)])
+                        if (inner == "NonNull" || inner == "Box") && inner_args.len() == 1 =>
+                    {
+                        self.declaration(&args[0], declarator, is_const)
+                    }
+                    ("NonNull" | "Box", [pointee]) => {
+                        let pointer = Type::Pointer {
+                            mutable: true,
+                            pointee: Box::new(pointee.clone()),
+                        };
+                        self.declaration(&pointer, declarator, is_const)
+                    }
+                    (name, []) => {
+                        let c_type = self.c_type(name)?;
+                        Ok(format_declaration(qualifier, &c_type, &declarator))
+                    }
+                    (name, _) => bail!("Unsupported generic type {}", name),
+                },
+                Type::Function { params, output } => {
+                    let mut c_params = Vec::new();
+                    for param in params {
+                        c_params.push(self.declaration(param, String::new(), false)?);
+                    }
+                    let c_params = if c_params.is_empty() {
+                        String::from("void")
+                    } else {
+                        c_params.join(", ")
+                    };
+
+                    let declarator = format!("(*{}{})({})", qualifier, declarator, c_params);
+                    match output.as_ref() {
+                        Type::Unit | Type::Never => Ok(format_declaration("", "void", &declarator)),
+                        output => self.declaration(output, declarator, false),
+                    }
+                }
+                Type::Unit => Ok(format_declaration(qualifier, "void", &declarator)),
+                Type::Never => bail!("The never type is only supported as return type"),
+            }
+        }
+
+        fn c_type(&mut self, name: &str) -> Result<String> {
+            if let Some(c_type) = builtin_type(name) {
+                return Ok(c_type.to_owned());
+            }
+            if self.defined.contains(name) {
+                return Ok(name.to_owned());
+            }
+            if self.declared.contains(name) {
+                if !self.opaque.iter().any(|opaque| opaque == name) {
+                    self.opaque.push(name.to_owned());
+                }
+                return Ok(name.to_owned());
+            }
+            bail!("Unsupported type {}", name)
+        }
+    }
+
+    fn format_declaration(qualifier: &str, c_type: &str, declarator: &str) -> String {
+        if declarator.is_empty() {
+            format!("{}{}", qualifier, c_type)
+        } else {
+            format!("{}{} {}", qualifier, c_type, declarator)
+        }
+    }
+}
+
+mod reports {
+    //! Aggregated results of executing a cargo command for multiple scripts
+    //!
+    //! The individual tests are recovered from the output of the libtest
+    //! harness, i.e., lines of the form `test NAME ... ok`.
+    //!
+    use std::{fmt::Write as _, path::PathBuf, time::Duration};
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ScriptResult {
+        pub script: PathBuf,
+        /// The cargo command executed, e.g., `test`
+        pub command: String,
+        pub exit_code: i32,
+        pub duration: Duration,
+        pub stdout: String,
+        pub stderr: String,
+    }
+
+    impl ScriptResult {
+        pub fn is_success(&self) -> bool {
+            self.exit_code == 0
+        }
+
+        pub fn tests(&self) -> Vec<TestCase> {
+            parse_test_output(&self.stdout)
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TestStatus {
+        Ok,
+        Failed,
+        Ignored,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct TestCase {
+        pub name: String,
+        pub status: TestStatus,
+        /// The captured output of failed tests
+        pub output: Option<String>,
+    }
+
+    /// Extract the individual tests from the output of the libtest harness
+    ///
+    pub fn parse_test_output(output: &str) -> Vec<TestCase> {
+        let mut result = Vec::new();
+        let mut failures = Vec::<(String, String)>::new();
+        let mut current: Option<(String, String)> = None;
+
+        for line in output.lines() {
+            let section = line
+                .strip_prefix("---- ")
+                .and_then(|line| line.strip_suffix(" stdout ----"));
+            if section.is_some() || line == "failures:" {
+                failures.extend(current.take());
+            }
+            if let Some(name) = section {
+                current = Some((name.to_owned(), String::new()));
+                continue;
+            }
+            if let Some((_, text)) = current.as_mut() {
+                text.push_str(line);
+                text.push('\n');
+                continue;
+            }
+
+            let test = line
+                .strip_prefix("test ")
+                .and_then(|line| line.rsplit_once(" ... "));
+            let (name, status) = match test {
+                Some(test) => test,
+                None => continue,
+            };
+            let status = match status {
+                "ok" => TestStatus::Ok,
+                "FAILED" => TestStatus::Failed,
+                status if status.starts_with("ignored") => TestStatus::Ignored,
+                _ => continue,
+            };
+            result.push(TestCase {
+                name: name.to_owned(),
+                status,
+                output: None,
+            });
+        }
+        failures.extend(current);
+
+        for test in &mut result {
+            if test.status != TestStatus::Failed {
+                continue;
+            }
+            let failure = failures.iter().find(|(name, _)| *name == test.name);
+            test.output = failure.map(|(_, text)| text.trim().to_owned());
+        }
+        result
+    }
+
+    /// Format the table of per-script results followed by a one-line summary
+    ///
+    pub fn format_summary(results: &[ScriptResult]) -> String {
+        let rows = results
+            .iter()
+            .map(|result| {
+                let status = if result.is_success() { "ok" } else { "FAILED" };
+                [
+                    result.script.display().to_string(),
+                    status.to_owned(),
+                    format_details(result),
+                    format!("{:.2}s", result.duration.as_secs_f64()),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut result = String::new();
+        for row in &rows {
+            let line = format!(
+                "{:w0$}  {:w1$}  {:w2$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+            );
+            let _ = writeln!(result, "{}", line.trim_end());
+        }
+
+        let succeeded = results.iter().filter(|result| result.is_success()).count();
+        let _ = writeln!(
+            result,
+            ":: {} of {} scripts succeeded",
+            succeeded,
+            results.len()
+        );
+        result
     }
 
-    impl From<&str> for LineStart {
-        fn from(line: &str) -> Self {
-            if line.starts_with("//! ```cargo") {
-                Self::ManifestStart
-            } else if line.starts_with("//! ```") {
-                Self::ManifestEnd
-            } else if line.starts_with("//!") {
-                Self::DocComment
-            } else {
-                Self::Other
+    fn format_details(result: &ScriptResult) -> String {
+        let tests = result.tests();
+        if !tests.is_empty() {
+            let count = |status| tests.iter().filter(|test| test.status == status).count();
+            format!(
+                "{} passed, {} failed, {} ignored",
+                count(TestStatus::Ok),
+                count(TestStatus::Failed),
+                count(TestStatus::Ignored),
+            )
+        } else if !result.is_success() {
+            format!("exit code {}", result.exit_code)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Format the results as a JUnit XML report with one test suite per script
+    ///
+    /// Scripts without individual tests, e.g., for `check`, and scripts that
+    /// failed outside of any test are reported as a test case named after the
+    /// command.
+    ///
+    pub fn format_junit(results: &[ScriptResult]) -> String {
+        let mut suites = String::new();
+        let mut total_tests = 0;
+        let mut total_failures = 0;
+        let mut total_time = Duration::default();
+
+        for result in results {
+            let script = result.script.display().to_string();
+            let mut tests = result.tests();
+
+            let has_failed_test = tests.iter().any(|test| test.status == TestStatus::Failed);
+            if tests.is_empty() || (!result.is_success() && !has_failed_test) {
+                tests.push(TestCase {
+                    name: format!("cargo {}", result.command),
+                    status: if result.is_success() {
+                        TestStatus::Ok
+                    } else {
+                        TestStatus::Failed
+                    },
+                    output: Some(format!(
+                        "exit code {}\n{}",
+                        result.exit_code,
+                        result.stderr.trim()
+                    )),
+                });
+            }
+
+            let count = |status| tests.iter().filter(|test| test.status == status).count();
+            let failures = count(TestStatus::Failed);
+            total_tests += tests.len();
+            total_failures += failures;
+            total_time += result.duration;
+
+            let _ = writeln!(
+                suites,
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+                escape_xml(&script),
+                tests.len(),
+                failures,
+                count(TestStatus::Ignored),
+                result.duration.as_secs_f64(),
+            );
+            for test in &tests {
+                let _ = write!(
+                    suites,
+                    "    <testcase name=\"{}\" classname=\"{}\"",
+                    escape_xml(&test.name),
+                    escape_xml(&script),
+                );
+                match test.status {
+                    TestStatus::Ok => {
+                        let _ = writeln!(suites, "/>");
+                    }
+                    TestStatus::Ignored => {
+                        let _ = writeln!(suites, ">\n      <skipped/>\n    </testcase>");
+                    }
+                    TestStatus::Failed => {
+                        let _ = writeln!(
+                            suites,
+                            ">\n      <failure message=\"failed\">{}</failure>\n    </testcase>",
+                            escape_xml(test.output.as_deref().unwrap_or_default()),
+                        );
+                    }
+                }
+            }
+            let _ = writeln!(suites, "  </testsuite>");
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <testsuites tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n\
+            {}\
+            </testsuites>\n",
+            total_tests,
+            total_failures,
+            total_time.as_secs_f64(),
+            suites,
+        )
+    }
+
+    fn escape_xml(text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => result.push_str("&amp;"),
+                '<' => result.push_str("&lt;"),
+                '>' => result.push_str("&gt;"),
+                '"' => result.push_str("&quot;"),
+                '\'' => result.push_str("&apos;"),
+                // control characters are not allowed in XML 1.0
+                c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+                c => result.push(c),
+            }
+        }
+        result
+    }
+}
+
+mod patterns {
+    //! File name patterns as used by the artifact filter
+    //!
+    //! Patterns support `*` (any sequence of characters), `?` (a single
+    //! character) and named placeholders `{name}` that match a non-empty
+    //! sequence of characters and can be used in templates.
+    //!
+    use anyhow::{bail, Result};
+
+    pub type Captures = Vec<(String, String)>;
+
+    #[derive(Debug, PartialEq)]
+    enum Token {
+        Literal(char),
+        Any,
+        One,
+        Capture(String),
+    }
+
+    /// Whether the string contains any wildcards or placeholders
+    ///
+    pub fn is_pattern(pattern: &str) -> bool {
+        parse_pattern(pattern)
+            .iter()
+            .any(|token| !matches!(token, Token::Literal(_)))
+    }
+
+    /// Match the name against the pattern and return the captured placeholders
+    ///
+    pub fn match_pattern(pattern: &str, name: &str) -> Option<Captures> {
+        let tokens = parse_pattern(pattern);
+        let chars = name.chars().collect::<Vec<_>>();
+        let mut captures = Vec::new();
+
+        if match_tokens(&tokens, &chars, &mut captures) {
+            Some(captures)
+        } else {
+            None
+        }
+    }
+
+    /// Replace the placeholders in the template with the captured values
+    ///
+    pub fn render_pattern(template: &str, captures: &[(String, String)]) -> Result<String> {
+        let mut result = String::new();
+        for token in parse_pattern(template) {
+            match token {
+                Token::Literal(c) => result.push(c),
+                Token::Capture(name) => {
+                    let value = captures.iter().find(|(key, _)| *key == name);
+                    match value {
+                        Some((_, value)) => result.push_str(value),
+                        None => bail!("Unknown placeholder {{{}}} in {}", name, template),
+                    }
+                }
+                Token::Any | Token::One => {
+                    bail!("Wildcards are not supported in templates: {}", template)
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn parse_pattern(pattern: &str) -> Vec<Token> {
+        let mut result = Vec::new();
+        let mut rest = pattern;
+
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '*' => result.push(Token::Any),
+                '?' => result.push(Token::One),
+                '{' => match rest.find('}') {
+                    Some(end) if end > 0 => {
+                        result.push(Token::Capture(rest[..end].to_owned()));
+                        rest = &rest[end + 1..];
+                    }
+                    _ => result.push(Token::Literal(c)),
+                },
+                c => result.push(Token::Literal(c)),
+            }
+        }
+        result
+    }
+
+    /// Match greedily, i.e., earlier wildcards consume as much as possible
+    ///
+    fn match_tokens(tokens: &[Token], chars: &[char], captures: &mut Captures) -> bool {
+        let (token, rest) = match tokens.split_first() {
+            Some(item) => item,
+            None => return chars.is_empty(),
+        };
+
+        match token {
+            Token::Literal(c) => {
+                chars.first() == Some(c) && match_tokens(rest, &chars[1..], captures)
+            }
+            Token::One => !chars.is_empty() && match_tokens(rest, &chars[1..], captures),
+            Token::Any => (0..=chars.len())
+                .rev()
+                .any(|split| match_tokens(rest, &chars[split..], captures)),
+            Token::Capture(name) => {
+                for split in (1..=chars.len()).rev() {
+                    let value = chars[..split].iter().collect::<String>();
+                    let previous = captures.iter().find(|(key, _)| key == name);
+                    if let Some((_, previous)) = previous {
+                        if *previous == value && match_tokens(rest, &chars[split..], captures) {
+                            return true;
+                        }
+                        continue;
+                    }
+
+                    captures.push((name.to_owned(), value));
+                    if match_tokens(rest, &chars[split..], captures) {
+                        return true;
+                    }
+                    captures.pop();
+                }
+                false
             }
         }
     }
@@ -1243,7 +7819,7 @@ mod manifest_parsing {
 
 mod util {
     use anyhow::{anyhow, bail, Result};
-    use std::ffi::OsStr;
+    use std::{ffi::OsStr, fs, path::Path, time::SystemTime};
 
     pub fn to_utf8_string(s: &OsStr) -> Result<String> {
         let result = s
@@ -1253,6 +7829,46 @@ mod util {
         Ok(result)
     }
 
+    /// Copy a file or a directory including all its contents
+    ///
+    pub fn copy_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<()> {
+        let src = src.as_ref();
+        let dst = dst.as_ref();
+
+        if src.is_dir() {
+            fs::create_dir_all(dst)?;
+            for entry in fs::read_dir(src)? {
+                let entry = entry?;
+                copy_all(entry.path(), dst.join(entry.file_name()))?;
+            }
+        } else {
+            fs::copy(src, dst)?;
+        }
+        Ok(())
+    }
+
+    /// Format the date part of a timestamp as YYYY-MM-DD (in UTC)
+    ///
+    pub fn format_date(time: SystemTime) -> String {
+        let secs = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = (secs / 86_400) as i64 + 719_468;
+        let era = days.div_euclid(146_097);
+        let doe = days.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+
     /// A format-like function that uses a function to lookup replacements
     ///
     pub fn format_dynamic<F>(template: &str, mut replacement: F) -> Result<String>
@@ -1260,10 +7876,7 @@ mod util {
         F: FnMut(&str) -> Result<String>,
     {
         fn find_from(haystack: &str, needle: char, offset: usize) -> Option<usize> {
-            match (&haystack[offset..]).find(needle) {
-                Some(res) => Some(res + offset),
-                None => None,
-            }
+            haystack[offset..].find(needle).map(|res| res + offset)
         }
 
         let mut res = String::new();
@@ -1326,6 +7939,7 @@ fn main() {
 //!
 //! [cargo-wop]
 //! default-action = ["build"]
+//! c-header = "%NAME%.h"
 //! ```
 
 #[no_mangle]
@@ -1334,42 +7948,41 @@ pub extern "C" fn add(a: i64, b: i64) -> i64 {
 }
 "##;
 
-    pub const TEMPLATE_PYMODULE: &str = r##"//! Python extension module %NAME%
+    pub const TEMPLATE_PYMODULE: &str = r##"//! Python extension module %CRATE_NAME%
 //!
 //! This module can be built with `cargo wop` and imported with Python:
 //!
 //! ```bash
 //! cargo wop %NAME%.rs
-//! python -c 'import %NAME%'
+//! python -c 'import %CRATE_NAME%'
 //! ```
 //!
+//! To build an installable wheel use `cargo wop wheel %NAME%.rs`.
+//!
 //! ```cargo
 //! [lib]
-//! name = "%NAME%"
+//! name = "%CRATE_NAME%"
 //! crate-type = ["cdylib"]
 //!
 //! [dependencies]
-//! pyo3 = { version = "0.13", features = ["extension-module"] }
+//! pyo3 = { version = "0.23", features = ["extension-module"] }
 //!
 //! [cargo-wop]
 //! default-action = ["build"]
-//! filter = { "lib%NAME%.so" = "%NAME%.so" }
+//! artifact-kind = "python-extension"
+//! filter = { "%CRATE_NAME%.pdb" = "" }
 //! ```
-#![allow(unused)]
-fn main() {
-    use pyo3::prelude::*;
-    use pyo3::wrap_pyfunction;
+use pyo3::prelude::*;
 
-    #[pyfunction]
-    fn add(a: i64, b: i64) -> PyResult<i64> {
-        Ok(a + b)
-    }
+#[pyfunction]
+fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
 
-    #[pymodule]
-    fn %NAME%(py: Python, m: &PyModule) -> PyResult<()> {
-        m.add_function(wrap_pyfunction!(add, m)?)?;
-        Ok(())
-    }
+#[pymodule]
+fn %CRATE_NAME%(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(add, m)?)?;
+    Ok(())
 }
 "##;
 
@@ -1397,6 +8010,194 @@ fn main() {
 pub extern "C" fn add(a: i64, b: i64) -> i64 {
     a + b
 }
+"##;
+
+    pub const TEMPLATE_WASM_BINDGEN: &str = r##"//! WASM module %NAME% with JS bindings
+//!
+//! This library can be built with `cargo wop`, the JS bindings are generated
+//! with the `wasm-bindgen` CLI, which must match the version of the crate:
+//!
+//! ```bash
+//! cargo wop %NAME%.rs
+//! ```
+//!
+//! ```cargo
+//! [lib]
+//! name = "%CRATE_NAME%"
+//! crate-type = ["cdylib"]
+//!
+//! [dependencies]
+//! wasm-bindgen = "0.2"
+//!
+//! [cargo-wop]
+//! default-action = ["build", "--target", "wasm32-unknown-unknown"]
+//! wasm-bindgen = { target = "web" }
+//! filter = { "%CRATE_NAME%.wasm" = "" }
+//! ```
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+"##;
+
+    pub const TEMPLATE_CLI: &str = r##"//! Command line tool %NAME%
+//!
+//! ```cargo
+//! [dependencies]
+//! clap = { version = "4", features = ["derive"] }
+//! ```
+use clap::Parser;
+
+/// Command line tool %NAME%
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Who to greet
+    #[arg(long, default_value = "world")]
+    name: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    println!("Hello {}", args.name);
+}
+"##;
+
+    pub const TEMPLATE_TOKIO: &str = r##"//! Async executable %NAME%
+//!
+//! ```cargo
+//! [dependencies]
+//! tokio = { version = "1", features = ["full"] }
+//! ```
+
+#[tokio::main]
+async fn main() {
+    println!("Hello world");
+}
+"##;
+
+    pub const TEMPLATE_PROC_MACRO: &str = r##"//! Procedural macros %CRATE_NAME%
+//!
+//! ```cargo
+//! [lib]
+//! name = "%CRATE_NAME%"
+//! proc-macro = true
+//!
+//! [dependencies]
+//! proc-macro2 = "1"
+//! quote = "1"
+//! syn = { version = "2", features = ["full"] }
+//!
+//! [cargo-wop]
+//! default-action = ["check"]
+//! ```
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Derive a `hello` function that prints the name of the type
+#[proc_macro_derive(Hello)]
+pub fn derive_hello(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+
+    let expanded = quote! {
+        impl #name {
+            pub fn hello() {
+                println!("Hello from {}", stringify!(#name));
+            }
+        }
+    };
+    expanded.into()
+}
+"##;
+
+    pub const TEMPLATE_BENCH: &str = r##"//! Benchmarks %NAME%
+//!
+//! Run the benchmarks with `cargo wop`:
+//!
+//! ```bash
+//! cargo wop %NAME%.rs
+//! ```
+//!
+//! ```cargo
+//! [[bench]]
+//! name = "%NAME%"
+//! harness = false
+//!
+//! [dev-dependencies]
+//! criterion = "0.5"
+//!
+//! [cargo-wop]
+//! default-action = ["bench"]
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn fibonacci(n: u64) -> u64 {
+    match n {
+        0 | 1 => 1,
+        n => fibonacci(n - 1) + fibonacci(n - 2),
+    }
+}
+
+fn bench_fibonacci(c: &mut Criterion) {
+    c.bench_function("fibonacci 20", |b| b.iter(|| fibonacci(black_box(20))));
+}
+
+criterion_group!(benches, bench_fibonacci);
+criterion_main!(benches);
+"##;
+
+    pub const TEMPLATE_STATICLIB: &str = r##"//! Static library %CRATE_NAME%
+//!
+//! This library can be built with `cargo wop` and linked into C programs using
+//! the generated header `%CRATE_NAME%.h`.
+//!
+//! ```cargo
+//! [lib]
+//! name = "%CRATE_NAME%"
+//! crate-type = ["staticlib"]
+//!
+//! [dependencies]
+//! # include additional dependencies here
+//!
+//! [cargo-wop]
+//! default-action = ["build"]
+//! filter = { "%CRATE_NAME%.pdb" = "" }
+//! c-header = "%CRATE_NAME%.h"
+//! ```
+
+#[no_mangle]
+pub extern "C" fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+"##;
+
+    pub const TEMPLATE_TEST: &str = r##"//! Integration tests %NAME%
+//!
+//! Run the tests with `cargo wop`:
+//!
+//! ```bash
+//! cargo wop %NAME%.rs
+//! ```
+//!
+//! ```cargo
+//! [[test]]
+//! name = "%NAME%"
+//!
+//! [dev-dependencies]
+//! # include the crates under test here
+//!
+//! [cargo-wop]
+//! default-action = ["test"]
+//! ```
+
+#[test]
+fn example() {
+    assert_eq!(1 + 1, 2);
+}
 "##;
 
     pub const HELP: &str = r##"cargo wop -- cargo without project
@@ -1411,19 +8212,61 @@ Run the rust source file as a script:
 Build the included targets, executables or libraries:
 
     cargo wop build SOURCE.rs [CARGO ARGUMENTS ...]
+    cargo wop build SOURCE.rs --out-dir DIR [CARGO ARGUMENTS ...]
+    cargo wop build SOURCE.rs --strict-filter [CARGO ARGUMENTS ...]
+    cargo wop build SOURCE.rs --all-targets-triples [CARGO ARGUMENTS ...]
 
-Per default run and build use release builds. Use the run-debug / build-debug
-commands for debug builds.
+Per default run and build use release builds, unless another profile is
+configured. Use build-PROFILE / run-PROFILE, e.g., build-debug, or pass
+--profile PROFILE to use another profile.
 
-cargo wop supports the following cargo commands:
+cargo wop knows the following cargo commands:
 
-    bench check clean clippy fmt install locate-project metadata pkgid tree
-    test verify-project
+    bench check clean clippy doc expand fix fmt install locate-project
+    metadata miri nextest pkgid rustc rustdoc tree test udeps update
+    verify-project
 
 They can be executed as
 
     cargo wop COMMAND SOURCE.rs [CARGO ARGUMENTS ...]
 
+Any other command is forwarded to cargo with the --manifest-path argument.
+All commands accept --toolchain TOOLCHAIN to select the rustup toolchain.
+
+Run the doc examples of a script, also for scripts without a library target:
+
+    cargo wop test SOURCE.rs --doc [CARGO ARGUMENTS ...]
+
+Run test, check or clippy for multiple scripts and summarize the results:
+
+    cargo wop test SOURCE.rs SOURCE.rs ... [CARGO ARGUMENTS ...]
+    cargo wop test 'DIR/*.rs' [--parallel N] [--junit FILE] [CARGO ARGUMENTS ...]
+
+Create a publishable .crate file next to the script or publish it:
+
+    cargo wop package SOURCE.rs [CARGO ARGUMENTS ...]
+    cargo wop publish SOURCE.rs --registry REGISTRY [CARGO ARGUMENTS ...]
+
+Install scripts, list them, reinstall changed scripts and uninstall them:
+
+    cargo wop install SOURCE.rs [CARGO ARGUMENTS ...]
+    cargo wop installed
+    cargo wop install --all-stale [CARGO ARGUMENTS ...]
+    cargo wop uninstall SOURCE.rs [CARGO ARGUMENTS ...]
+
+Build a Python wheel from a script defining an extension module:
+
+    cargo wop wheel SOURCE.rs [--python-tag TAG] [--venv DIR] [--out-dir DIR]
+
+Generate the documentation and copy it into the given directory:
+
+    cargo wop doc SOURCE.rs --doc-dir DIR [CARGO ARGUMENTS ...]
+
+Run a task configured in the embedded manifest or list the tasks:
+
+    cargo wop task SOURCE.rs TASK
+    cargo wop tasks SOURCE.rs
+
 In addition the following extra commands are supported:
 
     cargo wop manifest SOURCE.rs        - Show the generated manifest file
@@ -1433,25 +8276,29 @@ In addition the following extra commands are supported:
                                           a new file
     cargo wop new TEMPLATE SOURCE.rs    - Create the file SOURCE.rs using the
                                           given template
+    cargo wop new TEMPLATE SOURCE.rs --var KEY=VALUE
+                                        - Create the file and set additional
+                                          template placeholders
+    cargo wop --dry-run COMMAND ...     - Print the cargo commands that would
+                                          be executed without running them
+    cargo wop explain COMMAND ...         (same as --dry-run)
     cargo wop help                      - Show this help text
     cargo wop --help
-"##;
-
-    pub const HELP_TEMPLATES: &str = r##"The following templates are available:
-
-- "--bin": an executable
-- "--lib": a library
-- "--pymodule": a library using PyO3 that compiles to a Python extension module
-- "--wasm": a standalone wasm32 module
 "##;
 }
 
 #[cfg(test)]
 mod test_parse_args {
-    use super::argparse::{Args, CargoCall, DefaultAction};
+    use super::argparse::{Args, CargoCall, DefaultAction, NewFile, Profile};
     use anyhow::Result;
     use std::{ffi::OsString, path::PathBuf};
 
+    /// Helper to construct calls that use the configured default profile
+    fn default_profile(mut call: CargoCall) -> CargoCall {
+        call.options.profile = Profile::Default;
+        call
+    }
+
     /// Helper to simplify using parse_args
     fn parse_args(args: &[&str]) -> Result<Args> {
         let mut os_args = Vec::<OsString>::new();
@@ -1476,7 +8323,9 @@ mod test_parse_args {
     fn example_run_debug() {
         assert_eq!(
             parse_args(&["wop", "run-debug", "example.rs"]).unwrap(),
-            CargoCall::new("run", "example.rs").into_args(),
+            CargoCall::new("run", "example.rs")
+                .with_profile("dev")
+                .into_args(),
         );
     }
 
@@ -1484,9 +8333,7 @@ mod test_parse_args {
     #[test]
     fn example2() {
         let actual = parse_args(&["wop", "build", "example.rs"]).unwrap();
-        let expected = CargoCall::new("build", "example.rs")
-            .with_args(&["--release"])
-            .into_args();
+        let expected = default_profile(CargoCall::new("build", "example.rs")).into_args();
 
         assert_eq!(actual, expected);
     }
@@ -1495,39 +8342,354 @@ mod test_parse_args {
     #[test]
     fn cargo_args() {
         let actual = parse_args(&["wop", "run", "example.rs", "--verbose", "--", "arg"]).unwrap();
-        let expected = CargoCall::new("run", "example.rs")
-            .with_args(&["--verbose", "--release", "--", "arg"])
-            .into_args();
+        let expected = default_profile(CargoCall::new("run", "example.rs").with_args([
+            "--verbose",
+            "--",
+            "arg",
+        ]))
+        .into_args();
 
         assert_eq!(actual, expected);
     }
 
     /// Test parsing run-debug commands with additional arguments for cargo
     #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn cargo_args_debug() {
         let actual =
             parse_args(&["wop", "run-debug", "example.rs", "--verbose", "--", "arg"]).unwrap();
         let expected = CargoCall::new("run", "example.rs")
+            .with_profile("dev")
             .with_args(&["--verbose", "--", "arg"])
             .into_args();
 
-        assert_eq!(actual, expected);
+        assert_eq!(actual, expected);
+    }
+
+    /// Test parsing build and run commands with custom profiles
+    #[test]
+    fn custom_profiles() {
+        assert_eq!(
+            parse_args(&["wop", "build-bench", "example.rs", "--out-dir=bin"]).unwrap(),
+            {
+                let mut expected = CargoCall::new("build", "example.rs").with_profile("bench");
+                expected.options.out_dir = Some(PathBuf::from("bin"));
+                expected.into_args()
+            },
+        );
+        // other suffixes are resolved against the manifest during execution
+        assert_eq!(
+            parse_args(&["wop", "build-profiling", "example.rs", "--out-dir=bin"]).unwrap(),
+            CargoCall::new("build-profiling", "example.rs")
+                .with_args(["--out-dir=bin"])
+                .into_args(),
+        );
+        assert_eq!(
+            parse_args(&["wop", "run-release", "example.rs", "--", "arg"]).unwrap(),
+            CargoCall::new("run", "example.rs")
+                .with_profile("release")
+                .with_args(["--", "arg"])
+                .into_args(),
+        );
+
+        // explicit profiles replace the profile selected by cargo-wop
+        for args in [
+            ["--profile", "dev"],
+            ["--profile=dev", "-v"],
+            ["--release", "-v"],
+        ] {
+            let mut full_args = vec!["wop", "build-bench", "example.rs"];
+            full_args.extend(args);
+            assert_eq!(
+                parse_args(&full_args).unwrap(),
+                CargoCall::new("build", "example.rs")
+                    .with_args(args)
+                    .into_args(),
+            );
+        }
+        assert_eq!(
+            parse_args(&[
+                "wop",
+                "run",
+                "example.rs",
+                "--profile",
+                "dev",
+                "--",
+                "--release"
+            ])
+            .unwrap(),
+            CargoCall::new("run", "example.rs")
+                .with_args(["--profile", "dev", "--", "--release"])
+                .into_args(),
+        );
+    }
+
+    /// Test parsing test commands for multiple scripts
+    #[test]
+    fn multiple_scripts() {
+        assert_eq!(
+            parse_args(&["wop", "test", "a.rs", "b.rs", "--", "--nocapture"]).unwrap(),
+            Args::MultiCargoCall(
+                CargoCall::new("test", "a.rs").with_args(["--", "--nocapture"]),
+                vec![PathBuf::from("a.rs"), PathBuf::from("b.rs")],
+            ),
+        );
+        assert_eq!(
+            parse_args(&["wop", "clippy", "tests/*.rs", "--parallel", "4"]).unwrap(),
+            Args::MultiCargoCall(
+                {
+                    let mut expected = CargoCall::new("clippy", "tests/*.rs");
+                    expected.options.parallel = Some(4);
+                    expected
+                },
+                vec![PathBuf::from("tests/*.rs")],
+            ),
+        );
+        assert_eq!(
+            parse_args(&["wop", "check", "a.rs", "--junit=report.xml"]).unwrap(),
+            Args::MultiCargoCall(
+                {
+                    let mut expected = CargoCall::new("check", "a.rs");
+                    expected.options.junit = Some(PathBuf::from("report.xml"));
+                    expected
+                },
+                vec![PathBuf::from("a.rs")],
+            ),
+        );
+
+        // a single script keeps the direct cargo call
+        assert_eq!(
+            parse_args(&["wop", "test", "a.rs", "filter"]).unwrap(),
+            CargoCall::new("test", "a.rs")
+                .with_args(["filter"])
+                .into_args(),
+        );
+        // other commands accept a single script only
+        assert_eq!(
+            parse_args(&["wop", "run", "a.rs", "b.rs"]).unwrap(),
+            default_profile(CargoCall::new("run", "a.rs").with_args(["--", "b.rs"])).into_args(),
+        );
+        assert!(parse_args(&["wop", "test", "a.rs", "--parallel", "0"]).is_err());
+    }
+
+    /// Test parsing manifest commands
+    #[test]
+    fn manifest_example() {
+        let actual = parse_args(&["wop", "manifest", "example.rs"]).unwrap();
+        let expected = Args::Manifest(PathBuf::from("example.rs"));
+
+        assert_eq!(actual, expected);
+    }
+
+    /// Test that manifest commands with more than one argument are rejected
+    #[test]
+    fn manifest_example_error() {
+        let actual = parse_args(&["wop", "manifest", "example.rs", "second-arg"]);
+        assert!(actual.is_err());
+    }
+
+    /// Test that unknown commands are forwarded to cargo unchanged
+    #[test]
+    fn forward_unknown_commands() {
+        assert_eq!(
+            parse_args(&["wop", "udeps", "example.rs", "--all-targets"]).unwrap(),
+            CargoCall::new("udeps", "example.rs")
+                .with_args(["--all-targets"])
+                .into_args(),
+        );
+        assert_eq!(
+            parse_args(&["wop", "nextest", "example.rs", "run", "--", "arg"]).unwrap(),
+            CargoCall::new("nextest", "example.rs")
+                .with_args(["run", "--", "arg"])
+                .into_args(),
+        );
+        assert!(parse_args(&["wop", "--unknown", "example.rs"]).is_err());
+        assert!(parse_args(&["wop", "expand"]).is_err());
+    }
+
+    /// Test that cargo-wop options are removed from the cargo arguments
+    #[test]
+    fn doc_options() {
+        let mut expected =
+            CargoCall::new("doc", "example.rs").with_args(["--open", "--", "--doc-dir"]);
+        expected.options.doc_dir = Some(PathBuf::from("docs"));
+
+        assert_eq!(
+            parse_args(&[
+                "wop",
+                "doc",
+                "example.rs",
+                "--doc-dir",
+                "docs",
+                "--open",
+                "--",
+                "--doc-dir"
+            ])
+            .unwrap(),
+            expected.into_args(),
+        );
+        assert!(parse_args(&["wop", "doc", "example.rs", "--doc-dir"]).is_err());
+
+        // options are only interpreted for the commands that support them
+        assert_eq!(
+            parse_args(&["wop", "check", "example.rs", "--doc-dir=docs"]).unwrap(),
+            CargoCall::new("check", "example.rs")
+                .with_args(["--doc-dir=docs"])
+                .into_args(),
+        );
+    }
+
+    /// Test parsing dry runs
+    #[test]
+    fn dry_run() {
+        assert_eq!(
+            parse_args(&["wop", "--dry-run", "build", "example.rs"]).unwrap(),
+            Args::DryRun(Box::new(
+                default_profile(CargoCall::new("build", "example.rs")).into_args()
+            )),
+        );
+        assert_eq!(
+            parse_args(&["wop", "explain", "example.rs"]).unwrap(),
+            Args::DryRun(Box::new(DefaultAction::new("example.rs").into_args())),
+        );
+        assert!(parse_args(&["wop", "--dry-run"]).is_err());
+    }
+
+    /// Test that the out dir is removed from the build arguments
+    #[test]
+    fn build_out_dir() {
+        let mut expected = default_profile(CargoCall::new("build", "example.rs"));
+        expected.options.out_dir = Some(PathBuf::from("bin"));
+
+        assert_eq!(
+            parse_args(&["wop", "build", "example.rs", "--out-dir=bin"]).unwrap(),
+            expected.into_args(),
+        );
+    }
+
+    /// Test that the strict filter flag is removed from the build arguments
+    #[test]
+    fn build_strict_filter() {
+        let mut expected = default_profile(CargoCall::new("build", "example.rs"));
+        expected.options.strict_filter = true;
+
+        assert_eq!(
+            parse_args(&["wop", "build", "example.rs", "--strict-filter"]).unwrap(),
+            expected.into_args(),
+        );
+    }
+
+    /// Test that the toolchain is extracted for all commands
+    #[test]
+    fn toolchain() {
+        let mut expected = CargoCall::new("check", "example.rs").with_args(["--all-targets"]);
+        expected.options.toolchain = Some(String::from("nightly"));
+        assert_eq!(
+            parse_args(&[
+                "wop",
+                "check",
+                "example.rs",
+                "--toolchain",
+                "nightly",
+                "--all-targets"
+            ])
+            .unwrap(),
+            expected.into_args(),
+        );
+
+        let mut expected =
+            default_profile(CargoCall::new("run", "example.rs").with_args(["--", "--toolchain"]));
+        expected.options.toolchain = Some(String::from("nightly"));
+        assert_eq!(
+            parse_args(&[
+                "wop",
+                "run",
+                "example.rs",
+                "--toolchain=nightly",
+                "--",
+                "--toolchain"
+            ])
+            .unwrap(),
+            expected.into_args(),
+        );
+    }
+
+    /// Test parsing wheel commands
+    #[test]
+    fn wheel() {
+        let mut expected = default_profile(CargoCall::new("wheel", "example.rs"));
+        expected.options.python_tag = Some(String::from("cp312"));
+        expected.options.venv = Some(PathBuf::from(".venv"));
+        assert_eq!(
+            parse_args(&[
+                "wop",
+                "wheel",
+                "example.rs",
+                "--python-tag",
+                "cp312",
+                "--venv=.venv"
+            ])
+            .unwrap(),
+            Args::WheelCargoCall(expected),
+        );
     }
 
-    /// Test parsing manifest commands
+    /// Test parsing task commands
     #[test]
-    fn manifest_example() {
-        let actual = parse_args(&["wop", "manifest", "example.rs"]).unwrap();
-        let expected = Args::Manifest(PathBuf::from("example.rs"));
+    fn tasks() {
+        assert_eq!(
+            parse_args(&["wop", "task", "example.rs", "precommit"]).unwrap(),
+            Args::Task(PathBuf::from("example.rs"), String::from("precommit")),
+        );
+        assert_eq!(
+            parse_args(&["wop", "tasks", "example.rs"]).unwrap(),
+            Args::ListTasks(PathBuf::from("example.rs")),
+        );
+        assert!(parse_args(&["wop", "task", "example.rs"]).is_err());
+    }
 
-        assert_eq!(actual, expected);
+    /// Test parsing the commands to manage installed scripts
+    #[test]
+    fn manage_installed() {
+        assert_eq!(
+            parse_args(&["wop", "installed"]).unwrap(),
+            Args::ListInstalled
+        );
+        assert_eq!(
+            parse_args(&["wop", "install", "--all-stale", "--locked"]).unwrap(),
+            Args::InstallStale(vec![OsString::from("--locked")]),
+        );
+        assert_eq!(
+            parse_args(&["wop", "install", "--locked", "--all-stale"]).unwrap(),
+            Args::InstallStale(vec![OsString::from("--locked")]),
+        );
+        assert_eq!(
+            parse_args(&["wop", "uninstall", "example.rs"]).unwrap(),
+            CargoCall::new("uninstall", "example.rs").into_args(),
+        );
+        assert!(parse_args(&["wop", "installed", "example.rs"]).is_err());
     }
 
-    /// Test that manifest commands with more than one argument are rejected
+    /// Test parsing new commands with template variables
     #[test]
-    fn manifest_example_error() {
-        let actual = parse_args(&["wop", "manifest", "example.rs", "second-arg"]);
-        assert!(actual.is_err());
+    fn new_with_vars() {
+        let actual = parse_args(&[
+            "wop",
+            "new",
+            "--cli",
+            "--var",
+            "AUTHOR=me",
+            "example.rs",
+            "--var=LICENSE=MIT",
+        ])
+        .unwrap();
+        let expected = NewFile::new("--cli", "example.rs")
+            .with_vars(vec![("AUTHOR", "me"), ("LICENSE", "MIT")])
+            .into_args();
+
+        assert_eq!(actual, expected);
+        assert_eq!(parse_args(&["wop", "new"]).unwrap(), Args::ListTemplates);
+        assert!(parse_args(&["wop", "new", "--bin", "example.rs", "--var", "NOVALUE"]).is_err());
     }
 }
 
@@ -1621,3 +8783,686 @@ mod test_format_dynamic {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test_templates {
+    use super::{
+        execution_env::ExecutionEnv,
+        manifest_normalization::normalize_manifest,
+        manifest_parsing::parse_manifest,
+        templates::{find_templates, parse_description, render_template},
+    };
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+
+    /// An environment without any user or project templates
+    #[derive(Clone)]
+    struct TestEnv;
+
+    impl ExecutionEnv for TestEnv {
+        fn get_cargo_home_dir(&self) -> PathBuf {
+            PathBuf::from("/does-not-exist/.cargo")
+        }
+
+        fn get_working_directory(&self) -> PathBuf {
+            PathBuf::from("/does-not-exist")
+        }
+
+        fn normalize<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+            Ok(path.as_ref().into())
+        }
+    }
+
+    #[test]
+    fn builtin_templates_are_valid() -> Result<()> {
+        let vars = vec![(String::from("AUTHOR"), String::from("me"))];
+        let target = Path::new("/scripts/my-script.rs");
+
+        for template in find_templates(&TestEnv)? {
+            let source = render_template(&template.load()?, target, &vars)?;
+            let manifest = parse_manifest(source.as_bytes())?;
+            assert!(
+                manifest.get("cargo-wop").is_some() || manifest.get("dependencies").is_some(),
+                "Template {} does not contain an embedded manifest",
+                template.name
+            );
+
+            let manifest = normalize_manifest(manifest, target, &TestEnv)?;
+            assert!(manifest.get("cargo-wop").is_none());
+            assert!(manifest.get("package").is_some());
+            assert!(
+                script_targets(&manifest) > 0,
+                "Template {} does not build the script as a target",
+                template.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Count the targets of the normalized manifest that point to the script
+    fn script_targets(manifest: &toml::Value) -> usize {
+        let lib = manifest.get("lib").into_iter();
+        let arrays = ["bin", "test", "bench"]
+            .iter()
+            .filter_map(|key| manifest.get(*key).and_then(|t| t.as_array()))
+            .flatten();
+        lib.chain(arrays)
+            .filter(|target| {
+                target.get("path").and_then(|p| p.as_str()) == Some("/scripts/my-script.rs")
+            })
+            .count()
+    }
+
+    #[test]
+    fn bin_script_with_bench() -> Result<()> {
+        let manifest = toml::from_str(
+            r#"
+            [[bench]]
+            name = "other"
+            path = "benches/other.rs"
+            harness = false
+            "#,
+        )?;
+        let manifest = normalize_manifest(manifest, Path::new("/scripts/my-script.rs"), &TestEnv)?;
+
+        let bins = manifest.get("bin").and_then(|b| b.as_array()).unwrap();
+        assert_eq!(bins.len(), 1);
+        assert_eq!(
+            bins[0].get("path").and_then(|p| p.as_str()),
+            Some("/scripts/my-script.rs")
+        );
+        assert_eq!(script_targets(&manifest), 1);
+
+        // a bench target pointing to the script replaces the default binary
+        let manifest = toml::from_str("[[bench]]\nname = \"my-script\"\nharness = false")?;
+        let manifest = normalize_manifest(manifest, Path::new("/scripts/my-script.rs"), &TestEnv)?;
+        assert!(manifest.get("bin").is_none());
+        assert_eq!(script_targets(&manifest), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn render_placeholders() -> Result<()> {
+        let vars = vec![(String::from("GREETING"), String::from("hello"))];
+        assert_eq!(
+            render_template(
+                "%NAME% %CRATE_NAME% %EDITION% %GREETING%",
+                Path::new("foo/my-script.rs"),
+                &vars
+            )?,
+            "my-script my_script 2018 hello",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn user_vars_take_precedence() -> Result<()> {
+        let vars = vec![(String::from("NAME"), String::from("other"))];
+        assert_eq!(
+            render_template("%NAME%", Path::new("example.rs"), &vars)?,
+            "other"
+        );
+        assert!(render_template("%UNKNOWN%", Path::new("example.rs"), &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(
+            parse_description("//! A clap based CLI\n//!\nfn main() {}\n"),
+            Some(String::from("A clap based CLI")),
+        );
+        assert_eq!(parse_description("fn main() {}\n"), None);
+        assert_eq!(parse_description("//!\n"), None);
+    }
+}
+
+#[cfg(test)]
+mod test_format_date {
+    use super::util::format_date;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn examples() {
+        let date = |secs: u64| format_date(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_735_689_599), "2024-12-31");
+        assert_eq!(date(1_735_689_600), "2025-01-01");
+    }
+}
+
+#[cfg(test)]
+mod test_package_manifest {
+    use super::manifest_normalization::package_manifest;
+    use anyhow::Result;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn example() -> Result<()> {
+        let manifest = toml::from_str(
+            r#"
+            [package]
+            name = "example"
+            build = "/scripts/build.rs"
+            readme = "docs/Readme.md"
+
+            [lib]
+            path = "/scripts/example.rs"
+
+            [dependencies]
+            serde = "1.0"
+            local = { path = "/scripts/local", version = "0.2" }
+
+            [target.'cfg(unix)'.dev-dependencies]
+            other = { path = "/scripts/other", version = "0.3" }
+            "#,
+        )?;
+        let expected = toml::from_str(
+            r#"
+            [package]
+            name = "example"
+            build = "build.rs"
+            readme = "Readme.md"
+
+            [lib]
+            path = "src/lib.rs"
+
+            [dependencies]
+            serde = "1.0"
+            local = { version = "0.2" }
+
+            [target.'cfg(unix)'.dev-dependencies]
+            other = { version = "0.3" }
+            "#,
+        )?;
+
+        let layout = package_manifest(manifest, Path::new("/scripts/example.rs"))?;
+        assert_eq!(layout.manifest, expected);
+        assert_eq!(
+            layout.files,
+            vec![
+                (
+                    PathBuf::from("/scripts/example.rs"),
+                    PathBuf::from("src/lib.rs")
+                ),
+                (
+                    PathBuf::from("/scripts/build.rs"),
+                    PathBuf::from("build.rs")
+                ),
+                (
+                    PathBuf::from("/scripts/docs/Readme.md"),
+                    PathBuf::from("Readme.md")
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn executables_use_main() -> Result<()> {
+        let manifest = toml::from_str(
+            r#"
+            [package]
+            name = "example"
+
+            [[bin]]
+            path = "/scripts/example.rs"
+            "#,
+        )?;
+        let layout = package_manifest(manifest, Path::new("/scripts/example.rs"))?;
+        assert_eq!(
+            layout.manifest["bin"][0]["path"].as_str(),
+            Some("src/main.rs")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn path_dependencies_without_version_are_rejected() -> Result<()> {
+        let manifest = toml::from_str(
+            r#"
+            [package]
+            name = "example"
+
+            [lib]
+            path = "/scripts/example.rs"
+
+            [dependencies]
+            local = { path = "/scripts/local" }
+            "#,
+        )?;
+        assert!(package_manifest(manifest, Path::new("/scripts/example.rs")).is_err());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_install_records {
+    use super::install_records::{InstallRecord, InstallRecords};
+    use std::{env, fs, path::PathBuf};
+
+    #[test]
+    fn roundtrip() {
+        let dir = env::temp_dir().join(format!(
+            "wop-test-install-records-roundtrip-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("example.rs");
+        fs::write(&script, "fn main() {}").unwrap();
+
+        let mut records = InstallRecords::default();
+        records.insert(InstallRecord::new(&script, "example", vec!["example".into()]).unwrap());
+        records.insert(InstallRecord::new(&script, "example", vec!["other".into()]).unwrap());
+        records.store(&dir).unwrap();
+
+        let loaded = InstallRecords::load(&dir).unwrap();
+        let loaded = loaded.iter().collect::<Vec<_>>();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].script, script);
+        assert_eq!(loaded[0].binaries, vec![String::from("other")]);
+        assert!(!loaded[0].is_stale());
+
+        fs::write(&script, "fn main() { println!(); }").unwrap();
+        assert!(loaded[0].is_stale());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(InstallRecords::load(&PathBuf::from(&dir))
+            .unwrap()
+            .iter()
+            .next()
+            .is_none());
+    }
+}
+
+#[cfg(test)]
+mod test_patterns {
+    use super::patterns::{is_pattern, match_pattern, render_pattern};
+
+    fn captures(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn globs() {
+        assert!(is_pattern("*.pdb"));
+        assert!(is_pattern("lib{name}.so"));
+        assert!(!is_pattern("libexample.so"));
+
+        assert_eq!(match_pattern("*.pdb", "example.pdb"), Some(vec![]));
+        assert_eq!(match_pattern("*.pdb", "example.dll"), None);
+        assert_eq!(match_pattern("example.?ll", "example.dll"), Some(vec![]));
+        assert_eq!(
+            match_pattern("libexample.so", "libexample.so"),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            match_pattern("lib{name}.{ext}", "libexample.so"),
+            Some(captures(&[("name", "example"), ("ext", "so")])),
+        );
+        assert_eq!(
+            match_pattern("lib{name}.{ext}", "libexample.cpython.so"),
+            Some(captures(&[("name", "example.cpython"), ("ext", "so")])),
+        );
+        assert_eq!(match_pattern("lib{name}.so", "lib.so"), None);
+        assert_eq!(
+            match_pattern("{a}-{a}", "foo-foo"),
+            Some(captures(&[("a", "foo")]))
+        );
+        assert_eq!(match_pattern("{a}-{a}", "foo-bar"), None);
+    }
+
+    #[test]
+    fn templates() {
+        let values = captures(&[("name", "example"), ("ext", "so")]);
+        assert_eq!(
+            render_pattern("{name}.{ext}", &values).unwrap(),
+            "example.so"
+        );
+        assert_eq!(render_pattern("", &values).unwrap(), "");
+        assert!(render_pattern("{unknown}.so", &values).is_err());
+        assert!(render_pattern("*.so", &values).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_wheel {
+    use super::wheel::{escape_name, python_version, record_hash, Wheel, WheelTags};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(
+            record_hash(b""),
+            "47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU"
+        );
+        assert_eq!(
+            record_hash(&[b'a'; 1000]),
+            "Qe3s5C1j6Nm_UVqbppMuHCDLyfWl0TRkWttdsblzfqM"
+        );
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(escape_name("My-Package.name"), "my_package_name");
+        assert_eq!(escape_name("a--b"), "a_b");
+        assert_eq!(python_version("0.1.0").unwrap(), "0.1.0");
+        assert_eq!(python_version("1.0.0-alpha.1").unwrap(), "1.0.0a1");
+        assert_eq!(python_version("1.0.0-rc.2").unwrap(), "1.0.0rc2");
+        assert!(python_version("1.0.0-dev").is_err());
+        assert!(python_version("1.0.0+build").is_err());
+    }
+
+    #[test]
+    fn tags() {
+        let (tags, suffix) =
+            WheelTags::parse("cp312 cp312 linux_x86_64 .cpython-312-x86_64-linux-gnu.so\n")
+                .unwrap();
+        assert_eq!(tags.python, "cp312");
+        assert_eq!(tags.platform, "linux_x86_64");
+        assert_eq!(suffix, ".cpython-312-x86_64-linux-gnu.so");
+        assert!(WheelTags::parse("cp312").is_err());
+
+        let wheel = Wheel {
+            name: String::from("my-module"),
+            version: String::from("0.2.0"),
+            summary: None,
+            tags,
+            files: Vec::new(),
+        };
+        assert_eq!(
+            wheel.file_name().unwrap(),
+            "my_module-0.2.0-cp312-cp312-linux_x86_64.whl"
+        );
+    }
+
+    #[test]
+    fn write() {
+        let dir = std::env::temp_dir().join(format!("wop-test-wheel-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("example.whl");
+
+        let wheel = Wheel {
+            name: String::from("example"),
+            version: String::from("0.1.0"),
+            summary: Some(String::from("An example")),
+            tags: WheelTags::from_python_tag("cp312"),
+            files: vec![(String::from("example.so"), b"content".to_vec())],
+        };
+        wheel.write(&path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[..4], b"PK\x03\x04");
+        // the end of central directory record lists the module and the metadata files
+        let end = &data[data.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_c_header {
+    use super::c_header::{generate_header, HeaderConfig};
+
+    fn generate(source: &str) -> String {
+        let config = HeaderConfig {
+            no_includes: true,
+            ..HeaderConfig::default()
+        };
+        generate_header(source, &config).unwrap()
+    }
+
+    #[test]
+    fn functions() {
+        let source = r#"
+            /// Add two numbers
+            #[no_mangle]
+            pub extern "C" fn add(a: i64, b: i64) -> i64 { a + b }
+
+            #[export_name = "renamed"]
+            pub unsafe extern "C" fn original(data: *const *mut u8, len: usize) {}
+
+            #[no_mangle]
+            pub fn rust_abi(value: String) {}
+
+            pub extern "C" fn not_exported(value: String) {}
+
+            #[no_mangle]
+            pub extern "C" fn strings() -> bool { let s = "}"; let c = '{'; true }
+        "#;
+        assert_eq!(
+            generate(source),
+            concat!(
+                "/**\n * Add two numbers\n */\n",
+                "int64_t add(int64_t a, int64_t b);\n\n",
+                "void renamed(uint8_t *const *data, uintptr_t len);\n\n",
+                "bool strings(void);\n\n",
+            )
+        );
+    }
+
+    #[test]
+    fn types() {
+        let source = r#"
+            #[repr(C)]
+            pub struct Point<'a> { pub x: f32, pub name: &'a [c_char; 8] }
+
+            pub struct Handle(Vec<u8>);
+
+            #[repr(u8)]
+            pub enum Color { Red = 1, Green }
+
+            #[no_mangle]
+            pub extern "C" fn create(point: Option<&Point>, color: Color) -> Option<Box<Handle>> {
+                None
+            }
+
+            #[no_mangle]
+            pub static mut COUNT: u32 = 0;
+        "#;
+        assert_eq!(
+            generate(source),
+            concat!(
+                "typedef struct Handle Handle;\n\n",
+                "typedef struct Point {\n  float x;\n  const char (*name)[8];\n} Point;\n\n",
+                "enum Color {\n  Red = 1,\n  Green,\n};\ntypedef uint8_t Color;\n\n",
+                "Handle *create(const Point *point, Color color);\n\n",
+                "extern uint32_t COUNT;\n\n",
+            )
+        );
+    }
+
+    #[test]
+    fn definition_order() {
+        let source = r#"
+            #[repr(C)]
+            pub struct Line { pub start: Point, pub end: Point, pub style: *const Style }
+
+            #[repr(C)]
+            pub struct Style { pub color: Color }
+
+            #[repr(C)]
+            pub struct Point { pub x: f32, pub y: f32 }
+
+            #[repr(C)]
+            pub enum Color { Red, Green }
+        "#;
+        assert_eq!(
+            generate(source),
+            concat!(
+                "typedef struct Point {\n  float x;\n  float y;\n} Point;\n\n",
+                "typedef enum Color {\n  Red,\n  Green,\n} Color;\n\n",
+                "typedef struct Style {\n  Color color;\n} Style;\n\n",
+                "typedef struct Line {\n  Point start;\n  Point end;\n  const Style *style;\n} Line;\n\n",
+            )
+        );
+    }
+
+    #[test]
+    fn function_pointers() {
+        let source = r#"
+            #[repr(C)]
+            pub struct Handlers { pub on_event: extern "C" fn(code: i32) }
+
+            #[no_mangle]
+            pub extern "C" fn cb(f: Option<extern "C" fn(i32) -> i32>) {}
+
+            #[no_mangle]
+            pub unsafe extern "C" fn find(cmp: unsafe extern "C" fn(*const u8, *const u8) -> bool) -> Option<extern "C" fn()> { None }
+
+            #[no_mangle]
+            pub static HANDLER: extern "C" fn(f64) -> f64 = identity;
+        "#;
+        assert_eq!(
+            generate(source),
+            concat!(
+                "typedef struct Handlers {\n  void (*on_event)(int32_t);\n} Handlers;\n\n",
+                "void cb(int32_t (*f)(int32_t));\n\n",
+                "void (*find(bool (*cmp)(const uint8_t *, const uint8_t *)))(void);\n\n",
+                "extern double (*const HANDLER)(double);\n\n",
+            )
+        );
+
+        let source = "#[no_mangle]\npub extern \"C\" fn f(value: fn(i32)) {}";
+        assert!(generate_header(source, &HeaderConfig::default()).is_err());
+    }
+
+    #[test]
+    fn config() {
+        let config = HeaderConfig {
+            header: Some(String::from("/* header */")),
+            include_guard: Some(String::from("EXAMPLE_H")),
+            sys_includes: vec![String::from("stdio.h")],
+            no_includes: true,
+            documentation: false,
+            ..HeaderConfig::default()
+        };
+        let source = "/// doc\n#[no_mangle]\npub extern \"C\" fn f() {}";
+        assert_eq!(
+            generate_header(source, &config).unwrap(),
+            concat!(
+                "/* header */\n\n",
+                "#ifndef EXAMPLE_H\n#define EXAMPLE_H\n\n",
+                "#include <stdio.h>\n\n",
+                "void f(void);\n\n",
+                "#endif  /* EXAMPLE_H */\n",
+            )
+        );
+    }
+
+    #[test]
+    fn unsupported_types() {
+        let source = "#[no_mangle]\npub extern \"C\" fn f(value: Vec<u8>) {}";
+        assert!(generate_header(source, &HeaderConfig::default()).is_err());
+
+        let source = "#[no_mangle]\npub extern \"C\" fn f(value: Unknown) {}";
+        assert!(generate_header(source, &HeaderConfig::default()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_reports {
+    use super::reports::{
+        format_junit, format_summary, parse_test_output, ScriptResult, TestStatus,
+    };
+    use std::{path::PathBuf, time::Duration};
+
+    const TEST_OUTPUT: &str = "
+running 3 tests
+test tests::fails ... FAILED
+test tests::works ... ok
+test slow ... ignored, takes too long
+
+failures:
+
+---- tests::fails stdout ----
+thread 'tests::fails' panicked at example.rs:3:5:
+1 < 2
+
+failures:
+    tests::fails
+
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+";
+
+    fn script_result(script: &str, exit_code: i32, stdout: &str) -> ScriptResult {
+        ScriptResult {
+            script: PathBuf::from(script),
+            command: String::from("test"),
+            exit_code,
+            duration: Duration::from_millis(1500),
+            stdout: stdout.to_owned(),
+            stderr: String::from("error: could not compile"),
+        }
+    }
+
+    #[test]
+    fn test_output() {
+        let tests = parse_test_output(TEST_OUTPUT);
+        let summary = tests
+            .iter()
+            .map(|test| (test.name.as_str(), test.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("tests::fails", TestStatus::Failed),
+                ("tests::works", TestStatus::Ok),
+                ("slow", TestStatus::Ignored),
+            ]
+        );
+        assert_eq!(
+            tests[0].output.as_deref(),
+            Some("thread 'tests::fails' panicked at example.rs:3:5:\n1 < 2"),
+        );
+        assert_eq!(tests[1].output, None);
+    }
+
+    #[test]
+    fn summary() {
+        let results = [
+            script_result("example.rs", 101, TEST_OUTPUT),
+            script_result("ok.rs", 0, ""),
+            script_result("broken.rs", 101, ""),
+        ];
+        assert_eq!(
+            format_summary(&results),
+            "example.rs  FAILED  1 passed, 1 failed, 1 ignored  1.50s\n\
+            ok.rs       ok                                     1.50s\n\
+            broken.rs   FAILED  exit code 101                  1.50s\n\
+            :: 1 of 3 scripts succeeded\n",
+        );
+    }
+
+    #[test]
+    fn junit() {
+        let results = [
+            script_result("example.rs", 101, TEST_OUTPUT),
+            script_result("broken.rs", 101, ""),
+        ];
+        let report = format_junit(&results);
+
+        assert!(report.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(report.contains("<testsuites tests=\"4\" failures=\"2\" time=\"3.000\">"));
+        assert!(report.contains(
+            "<testsuite name=\"example.rs\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.500\">"
+        ));
+        assert!(report.contains("<testcase name=\"tests::works\" classname=\"example.rs\"/>"));
+        assert!(report.contains("<skipped/>"));
+        assert!(report.contains("panicked at example.rs:3:5:\n1 &lt; 2</failure>"));
+        assert!(report.contains(
+            "<testcase name=\"cargo test\" classname=\"broken.rs\">\n      \
+            <failure message=\"failed\">exit code 101\nerror: could not compile</failure>"
+        ));
+    }
+}